@@ -0,0 +1,277 @@
+//! An on-disk cache of resolved latest versions, so repeated runs don't refetch every package.
+
+use color_eyre::eyre::Error;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::registry::{Fetched, VersionManifest};
+
+pub const DEFAULT_TTL_MINUTES: u64 = 10;
+const CACHE_FILE: &str = "registry-cache.json";
+
+/// A cached latest version of a package, along with when and where it was fetched.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    manifest: VersionManifest,
+    /// The registry that was asked, which only counts as a hit for the same registry.
+    registry: String,
+    /// The registry that answered, which may have been a mirror.
+    source: String,
+    /// Seconds since the Unix epoch.
+    fetched_at: u64,
+}
+
+/// Latest version lookups keyed by package name, loaded from and saved to a JSON file.
+#[derive(Debug)]
+pub struct Cache {
+    path: PathBuf,
+    ttl: Duration,
+    /// TTLs overriding `ttl` for specific packages.
+    package_ttls: IndexMap<String, Duration>,
+    entries: Mutex<IndexMap<String, CacheEntry>>,
+    /// Names of the entries fetched during this run, the only ones `save` needs to write.
+    fresh: Mutex<Vec<String>>,
+}
+
+impl Cache {
+    /// Loads the cache at `path`. A missing, unreadable or corrupt file is an empty cache.
+    pub fn load(path: PathBuf, ttl: Duration) -> Self {
+        Cache {
+            entries: Mutex::new(read_entries(&path)),
+            path,
+            ttl,
+            package_ttls: IndexMap::new(),
+            fresh: Mutex::new(vec![]),
+        }
+    }
+
+    /// Caches the given packages for a different TTL than the rest.
+    pub fn with_package_ttls(mut self, package_ttls: IndexMap<String, Duration>) -> Self {
+        self.package_ttls = package_ttls;
+        self
+    }
+
+    /// The cached latest version of a package from `registry`, unless it has expired.
+    pub fn get(&self, package_name: &str, registry: &str) -> Option<Fetched<VersionManifest>> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries.get(package_name)?;
+        let ttl = self.package_ttls.get(package_name).unwrap_or(&self.ttl);
+        let age = now().saturating_sub(entry.fetched_at);
+
+        (entry.registry == registry && age < ttl.as_secs()).then(|| Fetched {
+            value: entry.manifest.clone(),
+            registry: entry.source.clone(),
+        })
+    }
+
+    /// The integrity the cache remembers for `latest`'s version of a package, if it differs from
+    /// the one just fetched, however old the entry is.
+    pub fn changed_integrity(
+        &self,
+        package_name: &str,
+        latest: &VersionManifest,
+    ) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        let previous = &entries.get(package_name)?.manifest;
+        let previous_integrity = previous.integrity()?;
+
+        (previous.version == latest.version
+            && latest
+                .integrity()
+                .is_some_and(|integrity| integrity != previous_integrity))
+        .then(|| previous_integrity.to_string())
+    }
+
+    /// Records a freshly fetched latest version.
+    pub fn insert(&self, package_name: &str, registry: &str, latest: &Fetched<VersionManifest>) {
+        let entry = CacheEntry {
+            manifest: latest.value.clone(),
+            registry: registry.to_string(),
+            source: latest.registry.clone(),
+            fetched_at: now(),
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(package_name.to_string(), entry);
+        self.fresh.lock().unwrap().push(package_name.to_string());
+    }
+
+    /// Writes the entries fetched during this run back to disk. The file is locked while it is
+    /// rewritten, and entries other processes saved in the meantime are kept, so parallel runs
+    /// sharing a cache don't clobber each other.
+    pub fn save(&self) -> Result<(), Error> {
+        let fresh = self.fresh.lock().unwrap();
+        if fresh.is_empty() {
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let lock = fs::File::create(self.path.with_extension("lock"))?;
+        lock.lock()?;
+
+        let mut on_disk = read_entries(&self.path);
+        let entries = self.entries.lock().unwrap();
+        for package_name in fresh.iter() {
+            if let Some(entry) = entries.get(package_name) {
+                on_disk.insert(package_name.clone(), entry.clone());
+            }
+        }
+
+        // Replacing the file in one step means readers never see it half written.
+        let temp_path = self
+            .path
+            .with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temp_path, serde_json::to_string(&on_disk)?)?;
+        fs::rename(&temp_path, &self.path)?;
+
+        lock.unlock()?;
+        Ok(())
+    }
+}
+
+/// Deletes the cache file, if there is one.
+pub fn clear(path: &Path) -> Result<(), Error> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Where the cache lives: the OS cache directory (`$XDG_CACHE_HOME`, `~/.cache`,
+/// `~/Library/Caches` or `%LOCALAPPDATA%`), falling back to the temp directory.
+pub fn default_path() -> PathBuf {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let cache_dir = match env::consts::OS {
+        "macos" => home.map(|home| home.join("Library").join("Caches")),
+        "windows" => env::var_os("LOCALAPPDATA").map(PathBuf::from),
+        _ => env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| home.map(|home| home.join(".cache"))),
+    };
+
+    cache_dir
+        .unwrap_or_else(env::temp_dir)
+        .join(env!("CARGO_PKG_NAME"))
+        .join(CACHE_FILE)
+}
+
+/// Reads the entries of a cache file, treating any problem with it as an empty cache.
+fn read_entries(path: &Path) -> IndexMap<String, CacheEntry> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn manifest(version: &str) -> Fetched<VersionManifest> {
+        Fetched {
+            value: serde_json::from_value(serde_json::json!({ "version": version })).unwrap(),
+            registry: "https://registry.npmjs.org/".to_string(),
+        }
+    }
+
+    /// A cache path in a fresh test directory. Its own directory doesn't exist yet, so saving
+    /// has to create it.
+    fn temp_cache_path(name: &str) -> (TestDir, PathBuf) {
+        let dir = TestDir::new(&format!("cache-{}", name));
+        let path = dir.join("cache").join(CACHE_FILE);
+        (dir, path)
+    }
+
+    #[test]
+    fn test_cache_round_trip_and_expiry() {
+        let (_dir, path) = temp_cache_path("round-trip");
+        let registry = "https://registry.npmjs.org/";
+
+        let cache = Cache::load(path.clone(), Duration::from_secs(600));
+        assert!(cache.get("react", registry).is_none());
+        cache.insert("react", registry, &manifest("18.2.0"));
+        cache.save().unwrap();
+
+        let cache = Cache::load(path.clone(), Duration::from_secs(600));
+        assert_eq!(
+            cache.get("react", registry).unwrap().value.version,
+            "18.2.0"
+        );
+        assert!(cache.get("react", "https://npm.mycompany.com/").is_none());
+
+        let cache = Cache::load(path.clone(), Duration::ZERO);
+        assert!(cache.get("react", registry).is_none());
+
+        clear(&path).unwrap();
+        clear(&path).unwrap();
+    }
+
+    #[test]
+    fn test_package_ttl_overrides_global() {
+        let (_dir, path) = temp_cache_path("package-ttl");
+        let registry = "https://registry.npmjs.org/";
+
+        let mut package_ttls = IndexMap::new();
+        package_ttls.insert("@types/node".to_string(), Duration::from_secs(3600));
+        let cache = Cache::load(path, Duration::ZERO).with_package_ttls(package_ttls);
+        cache.insert("@types/node", registry, &manifest("20.5.0"));
+        cache.insert("react", registry, &manifest("18.2.0"));
+
+        assert!(cache.get("@types/node", registry).is_some());
+        assert!(cache.get("react", registry).is_none());
+    }
+
+    #[test]
+    fn test_corrupt_cache_is_a_miss() {
+        let (_dir, path) = temp_cache_path("corrupt");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, "{ not json").unwrap();
+
+        let cache = Cache::load(path.clone(), Duration::from_secs(600));
+        assert!(cache.get("react", "https://registry.npmjs.org/").is_none());
+    }
+
+    #[test]
+    fn test_parallel_writers_keep_each_others_entries() {
+        let (_dir, path) = temp_cache_path("parallel");
+        let registry = "https://registry.npmjs.org/";
+
+        let writers: Vec<_> = (0..8)
+            .map(|writer| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    let cache = Cache::load(path, Duration::from_secs(600));
+                    cache.insert(&format!("package-{}", writer), registry, &manifest("1.0.0"));
+                    cache.save().unwrap();
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let cache = Cache::load(path.clone(), Duration::from_secs(600));
+        for writer in 0..8 {
+            assert!(cache
+                .get(&format!("package-{}", writer), registry)
+                .is_some());
+        }
+    }
+}
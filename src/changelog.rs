@@ -0,0 +1,128 @@
+//! Fetching release notes for proposed upgrades from GitHub releases.
+
+use color_eyre::eyre::Error;
+use reqwest::Client;
+use serde::Deserialize;
+
+pub const GITHUB_API_URL: &str = "https://api.github.com";
+/// How many lines of a release's notes are shown.
+const EXCERPT_LINES: usize = 5;
+/// How many of the most recent releases are searched for the proposed version.
+const RELEASES_PER_PAGE: usize = 20;
+
+#[derive(Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    #[serde(default)]
+    body: Option<String>,
+}
+
+/// Extracts the owner and repository name from a GitHub repository URL.
+pub fn github_repo(repository_url: &str) -> Option<(String, String)> {
+    let path = repository_url
+        .strip_prefix("https://github.com/")
+        .or_else(|| repository_url.strip_prefix("http://github.com/"))?;
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    let owner = segments.next()?;
+    let repo = segments.next()?;
+    let repo = repo.strip_suffix(".git").unwrap_or(repo);
+
+    Some((owner.to_string(), repo.to_string()))
+}
+
+/// The releases API URL for a repository.
+pub fn releases_url(api_url: &str, owner: &str, repo: &str) -> String {
+    format!(
+        "{}/repos/{}/{}/releases?per_page={}",
+        api_url.trim_end_matches('/'),
+        owner,
+        repo,
+        RELEASES_PER_PAGE
+    )
+}
+
+/// Gets a short excerpt of the release notes for `version`, if the repository published a
+/// release tagged with it (as `1.2.3`, `v1.2.3` or `name@1.2.3`).
+pub async fn release_excerpt(
+    client: &Client,
+    api_url: &str,
+    owner: &str,
+    repo: &str,
+    version: &str,
+) -> Result<Option<String>, Error> {
+    let releases: Vec<Release> = client
+        .get(releases_url(api_url, owner, repo))
+        .header("User-Agent", env!("CARGO_PKG_NAME"))
+        .header("Accept", "application/vnd.github+json")
+        .send()
+        .await?
+        .error_for_status()?
+        .json()
+        .await?;
+
+    let release = releases.into_iter().find(|release| {
+        let tag = release.tag_name.rsplit('@').next().unwrap_or_default();
+        tag.trim_start_matches('v') == version
+    });
+
+    Ok(release.and_then(|release| release.body).map(|body| {
+        body.lines()
+            .map(str::trim_end)
+            .filter(|line| !line.is_empty())
+            .take(EXCERPT_LINES)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::mock_registry;
+
+    #[test]
+    fn test_github_repo() {
+        assert_eq!(
+            github_repo("https://github.com/facebook/react"),
+            Some(("facebook".to_string(), "react".to_string()))
+        );
+        assert_eq!(
+            github_repo("https://github.com/babel/babel/tree/main/packages/babel-core"),
+            Some(("babel".to_string(), "babel".to_string()))
+        );
+        assert_eq!(github_repo("https://gitlab.com/acme/ui"), None);
+        assert_eq!(github_repo("https://github.com/acme"), None);
+
+        assert_eq!(
+            releases_url(GITHUB_API_URL, "facebook", "react"),
+            "https://api.github.com/repos/facebook/react/releases?per_page=20"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_release_excerpt() {
+        let url = mock_registry(&[(
+            "/repos/acme/widget/releases?per_page=20",
+            200,
+            r#"[
+                {"tag_name": "widget@2.1.0", "body": "Unrelated"},
+                {"tag_name": "v2.0.0", "body": "Breaking changes:\n\n- Dropped node 12\n- New API"}
+            ]"#,
+        )])
+        .await;
+
+        let excerpt = release_excerpt(&Client::new(), &url, "acme", "widget", "2.0.0")
+            .await
+            .unwrap();
+        assert_eq!(
+            excerpt.as_deref(),
+            Some("Breaking changes:\n- Dropped node 12\n- New API")
+        );
+        assert_eq!(
+            release_excerpt(&Client::new(), &url, "acme", "widget", "3.0.0")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,232 @@
+//! Reading and editing JSON-with-comments manifests without reformatting them.
+
+use std::ops::Range;
+
+/// Blanks out `//` and `/* */` comments and trailing commas so the result parses as plain JSON.
+/// Every removed byte becomes a space (newlines are kept), so offsets into the result are
+/// offsets into the original contents.
+pub fn strip_comments(contents: &str) -> String {
+    let mut bytes = contents.as_bytes().to_vec();
+    let mut i = 0;
+    let mut last_comma: Option<usize> = None;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                last_comma = None;
+                i = string_end(&bytes, i);
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'/') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    bytes[i] = b' ';
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                let end = contents[i + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |end| i + 2 + end + 2);
+                for byte in &mut bytes[i..end] {
+                    if *byte != b'\n' {
+                        *byte = b' ';
+                    }
+                }
+                i = end;
+                continue;
+            }
+            b',' => last_comma = Some(i),
+            b'}' | b']' => {
+                if let Some(comma) = last_comma.take() {
+                    bytes[comma] = b' ';
+                }
+            }
+            byte if byte.is_ascii_whitespace() => {}
+            _ => last_comma = None,
+        }
+        i += 1;
+    }
+
+    // Only whole comments (or ASCII commas) were replaced, so the bytes are still valid UTF-8.
+    String::from_utf8(bytes).expect("stripping comments keeps valid UTF-8")
+}
+
+/// Replaces the value at `path` (a chain of object keys) with `value` encoded as a JSON string,
+/// leaving everything else in `contents` untouched. Returns `None` if the path doesn't exist.
+pub fn set_string(contents: &str, path: &[&str], value: &str) -> Option<String> {
+    let stripped = strip_comments(contents);
+    let mut span = 0..stripped.len();
+
+    for key in path {
+        let mut scanner = Scanner {
+            bytes: &stripped.as_bytes()[..span.end],
+            pos: span.start,
+        };
+        span = scanner
+            .members()?
+            .into_iter()
+            .rev()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value)?;
+    }
+
+    let mut edited = contents.to_string();
+    edited.replace_range(span, &serde_json::to_string(value).ok()?);
+    Some(edited)
+}
+
+/// Returns the index just past the string starting with the quote at `start`.
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+/// Walks comment-free JSON, recording where values sit rather than building them.
+struct Scanner<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Scanner<'_> {
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(u8::is_ascii_whitespace)
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn eat(&mut self, byte: u8) -> bool {
+        self.skip_whitespace();
+        if self.bytes.get(self.pos) == Some(&byte) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reads the object at the cursor, returning each key with the span of its value.
+    fn members(&mut self) -> Option<Vec<(String, Range<usize>)>> {
+        let mut members = vec![];
+        if !self.eat(b'{') {
+            return None;
+        }
+        if self.eat(b'}') {
+            return Some(members);
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.value()?;
+            let key: String = serde_json::from_slice(&self.bytes[key]).ok()?;
+            if !self.eat(b':') {
+                return None;
+            }
+            members.push((key, self.value()?));
+
+            if self.eat(b'}') {
+                return Some(members);
+            }
+            if !self.eat(b',') {
+                return None;
+            }
+        }
+    }
+
+    /// Skips over the value at the cursor, returning its span.
+    fn value(&mut self) -> Option<Range<usize>> {
+        self.skip_whitespace();
+        let start = self.pos;
+        match *self.bytes.get(self.pos)? {
+            b'"' => self.pos = string_end(self.bytes, self.pos),
+            b'{' => {
+                self.members()?;
+            }
+            b'[' => {
+                self.pos += 1;
+                if !self.eat(b']') {
+                    loop {
+                        self.value()?;
+                        if self.eat(b']') {
+                            break;
+                        }
+                        if !self.eat(b',') {
+                            return None;
+                        }
+                    }
+                }
+            }
+            _ => {
+                while self
+                    .bytes
+                    .get(self.pos)
+                    .is_some_and(|byte| !b",}] \t\r\n".contains(byte))
+                {
+                    self.pos += 1;
+                }
+            }
+        }
+
+        (self.pos > start).then_some(start..self.pos)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    const MANIFEST: &str = r#"{
+  // The package name.
+  "name": "example", /* inline */
+  "dependencies": {
+    "react": "^17.0.0", // pinned for now
+    "recoil": "0.1.0",
+  },
+}
+"#;
+
+    #[test]
+    fn test_strip_comments_parses_as_json() {
+        let stripped = strip_comments(MANIFEST);
+        assert_eq!(stripped.len(), MANIFEST.len());
+
+        let json: Value = serde_json::from_str(&stripped).unwrap();
+        assert_eq!(json["dependencies"]["react"], "^17.0.0");
+    }
+
+    #[test]
+    fn test_strip_comments_ignores_slashes_in_strings() {
+        let contents = r#"{"repository": "https://example.com/a//b", "x": "/* y */"}"#;
+        assert_eq!(strip_comments(contents), contents);
+    }
+
+    #[test]
+    fn test_set_string_keeps_comments() {
+        let edited = set_string(MANIFEST, &["dependencies", "react"], "^18.2.0").unwrap();
+
+        assert_eq!(
+            edited,
+            MANIFEST.replace(r#""react": "^17.0.0""#, r#""react": "^18.2.0""#)
+        );
+        assert!(edited.contains("// pinned for now"));
+        assert!(edited.contains("/* inline */"));
+    }
+
+    #[test]
+    fn test_set_string_missing_path() {
+        assert!(set_string(MANIFEST, &["devDependencies", "react"], "1.0.0").is_none());
+        assert!(set_string(MANIFEST, &["dependencies", "vue"], "1.0.0").is_none());
+    }
+}
@@ -1,302 +1,6906 @@
-use clap::{arg, command};
-use color_eyre::eyre::Error;
+mod cache;
+mod changelog;
+mod jsonc;
+mod registry;
+#[cfg(test)]
+mod test_dir;
+mod workspace;
+
+use cache::Cache;
+use clap::{arg, command, Arg, ArgMatches};
+use color_eyre::eyre::{eyre, Error};
+use futures::{future::join_all, stream, stream::FuturesUnordered, StreamExt};
 use indexmap::IndexMap;
 use pbr::ProgressBar;
+use registry::{
+    lockfile_registries, make_client, registry_host, resolve_registry, weekly_downloads, Packument,
+    Registry, DEFAULT_CONCURRENCY, DEFAULT_RETRIES, DEFAULT_TIMEOUT, DOWNLOADS_API_URL,
+};
+use reqwest::Client;
+use semver::{Version, VersionReq};
 use serde::Deserialize;
 use serde_json::Value;
 use tokio::task::JoinHandle;
 
-use std::{fs, io::Stdout, time::Instant};
+use std::{
+    env, fmt, fs,
+    io::{self, BufRead, IsTerminal, Stdout, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
 
-const API_URL: &str = "https://registry.npmjs.org/";
 const DEP_KEY: &str = "dependencies";
 const DEV_DEP_KEY: &str = "devDependencies";
+const PEER_DEP_KEY: &str = "peerDependencies";
+const OPTIONAL_DEP_KEY: &str = "optionalDependencies";
+/// The `--dep` names of the dependency sections, in the order they are checked.
+const DEP_TYPES: [(&str, &str); 4] = [
+    ("prod", DEP_KEY),
+    ("dev", DEV_DEP_KEY),
+    ("peer", PEER_DEP_KEY),
+    ("optional", OPTIONAL_DEP_KEY),
+];
+const RESOLUTIONS_KEY: &str = "resolutions";
+const CATALOG_KEY: &str = "catalog";
+const PNPM_WORKSPACE_FILE: &str = "pnpm-workspace.yaml";
+/// The JSON Schema of the `--json` report. A test infers the schema from what `updates_json`
+/// produces and fails on any difference, so the two can't drift apart.
+const REPORT_SCHEMA: &str = include_str!("report.schema.json");
+const DEFAULT_UPDATED_MESSAGE: &str =
+    "Updated {path}. Please install the updated packages. (npm/yarn/pnpm install)!";
+/// How many of the slowest package fetches are listed in verbose mode.
+const SLOWEST_FETCHES: usize = 5;
+/// Non-registry specs that `--validate` accepts without parsing them as semver.
+const SPEC_PROTOCOLS: [&str; 8] = [
+    "npm:",
+    "file:",
+    "link:",
+    "workspace:",
+    "catalog:",
+    "portal:",
+    "http://",
+    "https://",
+];
+/// The conventional order of dependency sections in a package.json.
+const SECTION_ORDER: [&str; 5] = [
+    DEP_KEY,
+    DEV_DEP_KEY,
+    PEER_DEP_KEY,
+    OPTIONAL_DEP_KEY,
+    RESOLUTIONS_KEY,
+];
+/// The lockfile npm writes next to package.json.
+const NPM_LOCKFILE: &str = "package-lock.json";
+/// The UTF-8 byte order mark some Windows editors put at the start of files.
+const BOM: char = '\u{feff}';
+/// Sets the default `--target` for shared scripts.
+const TARGET_ENV_VAR: &str = "NCU_TARGET";
+/// The exit status of `--fail-on-update` when dependencies are out of date.
+const UPDATES_AVAILABLE_EXIT_CODE: i32 = 1;
+/// Sets the registry when `--registry` isn't passed, as npm itself does.
+const REGISTRY_ENV_VAR: &str = "NPM_CONFIG_REGISTRY";
+/// A bearer token sent to the registry, for private registries.
+const TOKEN_ENV_VAR: &str = "NPM_TOKEN";
+const GIT_PREFIXES: [&str; 6] = ["git+", "git://", "git@", "github:", "gitlab:", "bitbucket:"];
 
-#[derive(Debug, Deserialize)]
-struct GetPackageResponse {
-    version: String,
+/// The parts of a `pnpm-workspace.yaml` that declare catalog versions.
+#[derive(Debug, Default, Deserialize)]
+struct PnpmWorkspace {
+    #[serde(default)]
+    catalog: IndexMap<String, String>,
+    #[serde(default)]
+    catalogs: IndexMap<String, IndexMap<String, String>>,
+}
+
+/// Catalog entries keyed by catalog: `None` is the default `catalog`, `Some(name)` an entry of
+/// `catalogs`.
+type Catalogs = Vec<(Option<String>, IndexMap<String, String>)>;
+
+/// How far an update moves a version, most significant first.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum Bump {
+    Major,
+    Minor,
+    Patch,
+    /// Either side isn't a plain semver version (tags, complex ranges).
+    Other,
+}
+
+impl fmt::Display for Bump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Bump::Major => "major",
+            Bump::Minor => "minor",
+            Bump::Patch => "patch",
+            Bump::Other => "other",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// How far an upgrade may move from the current version.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+enum Target {
+    /// Whatever the registry tags as latest.
+    #[default]
+    Latest,
+    /// The greatest version with the same major.
+    Minor,
+    /// The greatest version with the same major and minor.
+    Patch,
+}
+
+impl std::str::FromStr for Target {
+    type Err = Error;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        match target {
+            "latest" => Ok(Target::Latest),
+            "minor" => Ok(Target::Minor),
+            "patch" => Ok(Target::Patch),
+            other => Err(eyre!(
+                "Invalid target {}, expected latest, minor or patch",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Target::Latest => "latest",
+            Target::Minor => "minor",
+            Target::Patch => "patch",
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// Options controlling how each dependency is checked.
+#[derive(Debug, Default, Clone)]
+struct CheckOptions {
+    target: Target,
+    /// More targets from a `--target` list, whose versions are reported next to the first's.
+    compared_targets: Vec<Target>,
+    /// The target for `dependencies` from `--target-prod`, instead of `target`.
+    prod_target: Option<Target>,
+    /// The target for `devDependencies` from `--target-dev`, instead of `target`.
+    dev_target: Option<Target>,
+    min_satisfying: bool,
+    since_tag: bool,
+    /// Also find the greatest version each current range allows, what `npm update` installs.
+    wanted: bool,
+    /// Known-bad `(package, version)` pairs that must never be proposed.
+    excluded_versions: Vec<(String, String)>,
+    /// Propose bumps between prereleases of the same version, such as `rc.1` to `rc.2`.
+    pre: bool,
+    /// Leave `>=`/`>` specs untouched instead of bumping their floor.
+    leave_range_operators: bool,
+    /// Compare partial versions like `1` or `1.2` as `1.0.0` and `1.2.0`.
+    loose: bool,
+    /// A range every proposed version must satisfy, on top of the target.
+    to: Option<VersionReq>,
+    /// Only propose versions published before this ISO 8601 timestamp.
+    published_before: Option<String>,
+    /// Only report updates whose latest version was published more than this many days after
+    /// the current one.
+    behind_by_days: Option<u64>,
+    /// The versions an external `--policy-url` allows, per package. Packages it doesn't list are
+    /// unrestricted.
+    allowed_versions: IndexMap<String, Vec<String>>,
+    /// Only packages matching one of these patterns are checked, if any are given.
+    filter: Vec<String>,
+    /// Packages matching one of these patterns are never checked, even if they match `filter`.
+    reject: Vec<String>,
+    /// Dependency sections left out with `--dep`.
+    skipped_sections: Vec<&'static str>,
+    /// The `(os, cpu)` that optional dependencies must support, in npm's naming, if they should
+    /// be checked against the current platform.
+    platform: Option<(String, String)>,
 }
 
 #[derive(Debug)]
 struct PackageUpdateData {
     package_name: String,
+    /// The name the package was looked up under: what an `npm:` alias points at, or the package
+    /// a resolution key such as `**/left-pad` is for.
+    query_name: String,
     old_version: String,
     new_version: String,
-    dev: bool,
+    /// The package.json section (or `catalog`) the dependency was declared in.
+    section: &'static str,
+    /// The registry the new version was found in.
+    registry: String,
+    /// Every version published since the current one, when requested with `--since-tag`.
+    since: Vec<String>,
+    /// The greatest version the current range allows, when requested with `--diff-only`.
+    wanted: Option<String>,
+    /// The version each of the other `--target`s would move to, if any.
+    compared: Vec<(Target, Option<String>)>,
+    /// The deprecation message of the new version, if it has been deprecated.
+    deprecated: Option<String>,
+}
+
+/// How progress is reported while packages are checked.
+enum Progress {
+    Bar(Box<ProgressBar<Stdout>>),
+    /// One JSON event per completed package, written as a line to `out`.
+    Json {
+        done: u64,
+        total: u64,
+        out: Box<dyn Write>,
+    },
+}
+
+/// The outcome of checking a single dependency against the registry.
+#[derive(Debug)]
+enum PackageCheck {
+    Update(PackageUpdateData),
+    Skipped {
+        package_name: String,
+        reason: SkipReason,
+    },
+    Failed {
+        package_name: String,
+        error: String,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+enum SkipReason {
+    GitDependency,
+    AlreadyLatest,
+    RangeSatisfiable,
+    RangeOperator,
+    PrereleaseOnly,
+    /// A tag, wildcard, local path or protocol spec that has no version to bump.
+    UnsupportedSpec,
+    /// An optional dependency whose latest version doesn't support the current platform.
+    OtherPlatform,
+    /// The latest version came out less than `--behind-by` days after the current one.
+    RecentEnough,
+    /// Left out by `--filter` or `--reject`, so never looked up.
+    Rejected,
+}
+
+impl SkipReason {
+    /// A stable identifier for the reason, used in `--json` output.
+    fn code(&self) -> &'static str {
+        match self {
+            SkipReason::GitDependency => "git-dependency",
+            SkipReason::AlreadyLatest => "up-to-date",
+            SkipReason::RangeSatisfiable => "range-satisfiable",
+            SkipReason::RangeOperator => "range-operator",
+            SkipReason::PrereleaseOnly => "prerelease-only",
+            SkipReason::UnsupportedSpec => "unsupported-spec",
+            SkipReason::OtherPlatform => "other-platform",
+            SkipReason::RecentEnough => "recent-enough",
+            SkipReason::Rejected => "rejected",
+        }
+    }
+}
+
+impl CheckOptions {
+    /// The target for the dependencies of a section.
+    fn target_for(&self, section: &str) -> Target {
+        match section {
+            DEP_KEY => self.prod_target,
+            DEV_DEP_KEY => self.dev_target,
+            _ => None,
+        }
+        .unwrap_or(self.target)
+    }
+
+    fn is_excluded(&self, package_name: &str, version: &str) -> bool {
+        self.excluded_versions
+            .iter()
+            .any(|(name, excluded)| name == package_name && excluded == version)
+    }
+
+    /// Whether a package should be checked at all under `--filter` and `--reject`.
+    fn is_checked(&self, package_name: &str) -> bool {
+        let matches_any = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| matches_pattern(package_name, pattern))
+        };
+        (self.filter.is_empty() || matches_any(&self.filter)) && !matches_any(&self.reject)
+    }
+
+    /// Whether a version may be proposed, being neither excluded, disallowed by policy nor
+    /// outside the `--to` range.
+    fn is_allowed(&self, package_name: &str, version: &str) -> bool {
+        !self.is_excluded(package_name, version)
+            && self
+                .to
+                .as_ref()
+                .is_none_or(|to| Version::parse(version).is_ok_and(|version| to.matches(&version)))
+            && self
+                .allowed_versions
+                .get(package_name)
+                .is_none_or(|allowed| allowed.iter().any(|allowed| allowed == version))
+    }
+}
+
+impl PackageCheck {
+    fn package_name(&self) -> &str {
+        match self {
+            PackageCheck::Update(update) => &update.package_name,
+            PackageCheck::Skipped { package_name, .. } => package_name,
+            PackageCheck::Failed { package_name, .. } => package_name,
+        }
+    }
+
+    /// The latest version found for the package, given its declared range.
+    fn latest<'a>(&'a self, range: &'a str) -> Option<&'a str> {
+        match self {
+            PackageCheck::Update(update) => Some(update.new_version.trim_start_matches(['^', '~'])),
+            PackageCheck::Skipped {
+                reason: SkipReason::AlreadyLatest,
+                ..
+            } => Some(range.trim_start_matches(['^', '~'])),
+            _ => None,
+        }
+    }
+
+    /// Describes why the package was or wasn't updated, as shown by `--explain`.
+    fn explain(&self, options: &CheckOptions) -> String {
+        match self {
+            PackageCheck::Update(update) => format!(
+                "updated: latest {} > current {} within target {}",
+                update.new_version.trim_start_matches(['^', '~']),
+                update.old_version.trim_start_matches(['^', '~']),
+                options.target_for(update.section)
+            ),
+            PackageCheck::Skipped { reason, .. } => match reason {
+                SkipReason::GitDependency => "skipped: git dependency".to_string(),
+                SkipReason::AlreadyLatest => "skipped: already at latest".to_string(),
+                SkipReason::RangeSatisfiable => "skipped: current range is satisfiable".to_string(),
+                SkipReason::RangeOperator => "skipped: comparison range left as is".to_string(),
+                SkipReason::UnsupportedSpec => "skipped: not a plain version range".to_string(),
+                SkipReason::OtherPlatform => {
+                    "skipped: latest version doesn't support this platform".to_string()
+                }
+                SkipReason::RecentEnough => {
+                    "skipped: current version isn't far enough behind latest".to_string()
+                }
+                SkipReason::PrereleaseOnly => {
+                    "skipped: only the prerelease tag changed".to_string()
+                }
+                SkipReason::Rejected => "skipped: rejected by filter".to_string(),
+            },
+            PackageCheck::Failed { error, .. } => format!("failed: {}", error),
+        }
+    }
+}
+
+/// The command line interface. The bare invocation checks (or with `-u` updates) package.json
+/// as it always has; the subcommands take the same options, which are global.
+fn cli() -> clap::Command<'static> {
+    let command = command!()
+    .arg(arg!([path] "Optional path to package.json"))
+    .arg(
+        arg!(
+            -u --update "Enables updating of dep versions in package.json"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --proxy <url> "Route registry requests through a proxy (http, https or socks5)"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --registry <url> "Registry to query instead of the public npm registry (or NPM_CONFIG_REGISTRY)"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --mirror <url> "Registry mirror to try first, falling back to the primary registry"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"cache-ttl" <minutes> "How long cached latest versions are used for (default 10)"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"max-age-cache-per-package" <spec> "Cache this <package>=<minutes> for a different time than --cache-ttl"
+        )
+        .required(false)
+        .multiple_occurrences(true),
+    )
+    .arg(
+        arg!(
+            --"no-cache" "Always ask the registry, neither reading nor writing the cache"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"clear-cache" "Delete the registry cache before checking"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --precheck "Make sure the registries are reachable before checking any packages"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --retries <count> "How many times to retry a registry request after a timeout or 5xx response (default 2)"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --timeout <seconds> "How long to wait on a registry request before retrying or giving up (default 30)"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"retry-budget" <count> "Cap the retries made across the whole run, on top of --retries"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --concurrency <count> "How many packages to look up at the same time (default 16)"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"concurrency-ramp" <count> "Start with this many lookups at once, allowing one more per lookup up to --concurrency"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"latest-source" <source> "Resolve latest from the /latest endpoint, or the packument's dist-tags"
+        )
+        .required(false)
+        .possible_values(["endpoint", "packument"])
+        .default_value("endpoint"),
+    )
+    .arg(
+        arg!(
+            --filter <patterns> "Only check packages matching these comma-separated patterns (* wildcards, or substrings)"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --reject <patterns> "Never check packages matching these comma-separated patterns, even if they match --filter"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --dep <types> "Comma-separated dependency sections to check: prod, dev, peer, optional (default all)"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"only-prod" "Only check dependencies, leaving out dev, peer and optional ones (the same as --dep prod)"
+        )
+        .required(false)
+        .conflicts_with("dep"),
+    )
+    .arg(
+        arg!(
+            --interactive "Ask before writing each update with -u (needs a terminal)"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --install "Run the package manager's install after writing updates, reverting the written files if it fails"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"install-command" <command> "Command --install runs instead of the detected package manager's install"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"ignore-scripts" "Pass --ignore-scripts to the install --install runs, so no lifecycle scripts run"
+        )
+        .required(false)
+        .conflicts_with("install-command"),
+    )
+    .arg(
+        arg!(
+            -y --"assume-yes" "Update, install and print a JSON summary without asking, failing on any error"
+        )
+        .required(false)
+        .conflicts_with("interactive"),
+    )
+    .arg(
+        arg!(
+            --"write-peer" "Also write peerDependencies updates, which are otherwise only reported"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"platform-match" "Skip optional dependencies whose latest version doesn't support this platform"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"registry-from-lockfile" "Query each package from the registry package-lock.json says it was installed from"
+        )
+        .required(false)
+        .conflicts_with_all(&["from-tarball", "from-git"]),
+    )
+    .arg(
+        arg!(
+            --"search-registries" <urls> "Comma-separated registries to search, using the greatest latest version found"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            -v --verbose "Print additional details about the run"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --target <targets> "Upgrade to the latest version, or the greatest within the same major (minor) or minor (patch). A list such as latest,minor also shows the others' versions. Defaults to $NCU_TARGET, then latest"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"target-prod" <target> "The target for dependencies, instead of --target"
+        )
+        .required(false)
+        .possible_values(["latest", "minor", "patch"]),
+    )
+    .arg(
+        arg!(
+            --"target-dev" <target> "The target for devDependencies, instead of --target"
+        )
+        .required(false)
+        .possible_values(["latest", "minor", "patch"]),
+    )
+    .arg(
+        arg!(
+            --to <range> "Only propose versions satisfying this range, e.g. '<19'"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --loose "Compare partial versions like 1 or 1.2 as 1.0.0 and 1.2.0"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --pre "Propose updates between prereleases of the same version"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"min-satisfying" "Only repair ranges that no longer match any published version"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --catalog "Also check the pnpm catalogs in the adjacent pnpm-workspace.yaml"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --backup "Save the original package.json as package.json.bak before writing"
+        )
+        .required(false)
+        .alias("manifest-backup"),
+    )
+    .arg(
+        arg!(
+            --"verify-after-write" "Re-read package.json after writing to confirm it is still valid JSON"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --bom "Start the written package.json with a UTF-8 byte order mark"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"no-bom" "Drop the byte order mark package.json started with when writing it"
+        )
+        .required(false)
+        .conflicts_with("bom"),
+    )
+    .arg(
+        arg!(
+            --"compare-to-installed" "Report the declared range, installed version and latest version side by side"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --message <template> "Message printed after updating, {path} is replaced with the package.json path"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"absolute-path" "Show the package.json path as an absolute path in messages"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"diff-only" "Also show the greatest version each current range allows, what npm update installs, without writing"
+        )
+        .required(false)
+        .conflicts_with_all(&["update", "assume-yes"]),
+    )
+    .arg(
+        arg!(
+            --"since-tag" "List every version published since the current one for outdated packages"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"no-update-if-same-major" "Report major updates but never write them to package.json"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"test-command" <command> "Command to run after updating, reverting the written files if it fails"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --format <format> "Output format for the update report"
+        )
+        .required(false)
+        .possible_values(["text", "github"])
+        .default_value("text"),
+    )
+    .arg(
+        arg!(
+            --"group-by-registry" "Group reported updates under the registry they came from"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"group-by-severity" "Group reported updates into major, minor, patch and other bumps"
+        )
+        .required(false)
+        .conflicts_with("group-by-registry"),
+    )
+    .arg(
+        arg!(
+            --"aggregate-by-registry" "Summarize packages, updates and auth failures per registry"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"exclude-version" <spec> "Never propose this <package>@<version>, choosing the next best version instead"
+        )
+        .required(false)
+        .multiple_occurrences(true),
+    )
+    .arg(
+        arg!(
+            --"policy-url" <url> "Only propose versions allowed by the JSON policy at this URL"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --before <date> "Only propose versions published before this date (YYYY-MM-DD)"
+        )
+        .required(false)
+        .alias("target-date"),
+    )
+    .arg(
+        arg!(
+            --"min-age" <days> "Only propose versions published at least this many days ago"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"behind-by" <days> "Only report packages whose latest version is more than this many days newer than the current one"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"range-operator-policy" <policy> "How to update `>=`/`>` specs: bump their floor to latest, or leave them"
+        )
+        .required(false)
+        .possible_values(["bump-floor", "leave"])
+        .default_value("bump-floor"),
+    )
+    .arg(
+        arg!(
+            --"names-only" "Only print the names of updatable packages, separated by spaces"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"changelog-fetch" <count> "Show GitHub release notes for the first <count> updates"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"repository-links-only" "Only list the source repository of each outdated package"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"fail-on-update" "Exit with status 1 when updates are available and not written with -u"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"exit-bitmask" "Exit with bits set for patch (1), minor (2) and major (4) updates"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"from-tarball" <url> "Audit the package.json of a package tarball (a URL or local file) instead, without writing. Needs tar on the PATH"
+        )
+        .required(false)
+        .conflicts_with_all(&["update", "workspaces", "from-git"]),
+    )
+    .arg(
+        arg!(
+            --"from-git" <url> "Audit the package.json of a git repository instead, without writing. Needs git on the PATH"
+        )
+        .required(false)
+        .conflicts_with_all(&["update", "workspaces"]),
+    )
+    .arg(
+        arg!(
+            --workspaces "Check every package.json under the given path, skipping node_modules"
+        )
+        .required(false)
+        .conflicts_with_all(&["json", "summary-json"]),
+    )
+    .arg(
+        arg!(
+            --"workspace-depth" <depth> "How many directories deep --workspaces looks for package.json files"
+        )
+        .required(false)
+        .default_value("3"),
+    )
+    .arg(
+        arg!(
+            --"parallel-files" <count> "With --workspaces, how many package.json files to check at once (their output may interleave)"
+        )
+        .required(false)
+        .default_value("1")
+        .conflicts_with("interactive"),
+    )
+    .arg(
+        arg!(
+            --graph <path> "With --workspaces, write the graph of workspace: dependencies as JSON, or DOT for .dot paths"
+        )
+        .required(false)
+        .requires("workspaces"),
+    )
+    .arg(
+        arg!(
+            --"summary-json" <path> "Also write a JSON summary of the run to the given file"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"include-deprecated-current" "Also report dependencies whose currently declared version is deprecated"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"fail-if-deprecated" "Exit with an error if any target version is deprecated"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --validate "Check that every dependency spec is well formed, without contacting a registry"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"sort-preview" "List the dependencies that sorting each section alphabetically would move, without writing"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"rewrite-protocol" <rules> "Rewrite spec prefixes without looking anything up, e.g. \"~=^,workspace:*=workspace:^\""
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"min-downloads" <count> "Flag updates of packages with fewer weekly downloads than this"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --json "Print the available updates as a single JSON object instead of one per line"
+        )
+        .required(false),
+    )
+    .arg(
+        arg!(
+            --"print-schema" "Print the JSON Schema of the --json report and exit"
+        )
+        .required(false)
+        .hide(true),
+    )
+    .arg(
+        arg!(
+            --progress <mode> "Show a progress bar, or stream JSON progress events to stderr"
+        )
+        .required(false)
+        .possible_values(["bar", "json"])
+        .default_value("bar"),
+    )
+    .arg(
+        arg!(
+            --explain "Explain why each package was or wasn't updated"
+        )
+        .required(false),
+    );
+
+    let options: Vec<&str> = command
+        .get_arguments()
+        .map(Arg::get_id)
+        .filter(|id| !["path", "help", "version"].contains(id))
+        .collect();
+    options
+        .into_iter()
+        .fold(command, |command, id| {
+            command.mut_arg(id, |arg| arg.global(true))
+        })
+        .subcommand(
+            clap::Command::new("check")
+                .about("Check package.json for updates, without writing")
+                .arg(arg!([path] "Optional path to package.json")),
+        )
+        .subcommand(
+            clap::Command::new("update")
+                .about("Check package.json for updates and write them")
+                .arg(arg!([path] "Optional path to package.json")),
+        )
+        .subcommand(
+            clap::Command::new("doctor")
+                .about("Diagnose the manifest, registry access, cache and package manager")
+                .arg(arg!([path] "Optional path to package.json")),
+        )
+        .subcommand(
+            clap::Command::new("query")
+                .about("Print the latest version of a package")
+                .arg(arg!(<package> "The package to look up")),
+        )
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
     let start = Instant::now();
 
-    let matches = command!()
-        .arg(arg!([path] "Optional path to package.json"))
-        .arg(
-            arg!(
-                -u --update "Enables updating of dep versions in package.json"
-            )
-            .required(false),
-        )
-        .get_matches();
+    let matches = cli().get_matches();
+    // The options are global, so a subcommand's matches hold everything that was passed.
+    let subcommand = matches
+        .subcommand()
+        .map(|(name, sub_matches)| (name.to_string(), sub_matches.clone()));
+    let (subcommand, matches) = match subcommand {
+        Some((name, sub_matches)) => (Some(name), sub_matches),
+        None => (None, matches),
+    };
+    let subcommand = subcommand.as_deref();
+    if matches.is_present("print-schema") {
+        print!("{}", REPORT_SCHEMA);
+        return Ok(());
+    }
+
+    let should_update = update_requested(subcommand, &matches)?;
+    // `conflicts_with("update")` only sees -u, not the `update` subcommand or -y.
+    if should_update && (matches.is_present("from-tarball") || matches.is_present("from-git")) {
+        return Err(eyre!(
+            "--from-tarball and --from-git only audit, they can't be used with `update`"
+        ));
+    }
+    let path = match subcommand {
+        Some("query") => "package.json",
+        _ => matches.value_of("path").unwrap_or("package.json"),
+    };
+    let json_output = matches.is_present("json");
+    if should_update && matches.is_present("interactive") && !io::stdin().is_terminal() {
+        return Err(eyre!(
+            "--interactive needs a terminal to ask on, run without it to write every update"
+        ));
+    }
+    let targets = resolve_targets(
+        matches.value_of("target"),
+        env::var(TARGET_ENV_VAR).ok().as_deref(),
+    )?;
+    let options = CheckOptions {
+        target: targets[0],
+        compared_targets: targets[1..].to_vec(),
+        prod_target: matches
+            .value_of("target-prod")
+            .map(str::parse)
+            .transpose()?,
+        dev_target: matches.value_of("target-dev").map(str::parse).transpose()?,
+        min_satisfying: matches.is_present("min-satisfying"),
+        since_tag: matches.is_present("since-tag"),
+        wanted: matches.is_present("diff-only"),
+        pre: matches.is_present("pre"),
+        excluded_versions: matches
+            .values_of("exclude-version")
+            .unwrap_or_default()
+            .map(parse_excluded_version)
+            .collect::<Result<_, _>>()?,
+        leave_range_operators: matches.value_of("range-operator-policy") == Some("leave"),
+        loose: matches.is_present("loose"),
+        to: matches
+            .value_of("to")
+            .map(|range| {
+                VersionReq::parse(&npm_range_to_semver(range))
+                    .map_err(|err| eyre!("Invalid --to range {}: {}", range, err))
+            })
+            .transpose()?,
+        filter: split_patterns(matches.value_of("filter")),
+        reject: split_patterns(matches.value_of("reject")),
+        skipped_sections: skipped_sections(dep_types(&matches))?,
+        platform: matches.is_present("platform-match").then(current_platform),
+        published_before: publish_cutoff(
+            matches.value_of("before"),
+            matches
+                .value_of("min-age")
+                .map(str::parse::<u64>)
+                .transpose()?,
+        )?,
+        behind_by_days: matches.value_of("behind-by").map(str::parse).transpose()?,
+        ..Default::default()
+    };
+
+    if matches.is_present("clear-cache") {
+        let cache_path = cache::default_path();
+        cache::clear(&cache_path)?;
+        if !json_output {
+            println!("Cleared the registry cache at {}.", cache_path.display());
+        }
+    }
+    let session = Session::new(&matches)?;
+    match subcommand {
+        Some("query") => return query_package(&matches, &session).await,
+        Some("doctor") => return doctor(path, &matches, &session).await,
+        _ => {}
+    }
+
+    // Audited manifests are fetched into a scratch directory, checked read-only and thrown away
+    // when the directory is dropped, however the check ends.
+    let audited = if let Some(tarball) = matches.value_of("from-tarball") {
+        // Like the changelog client, this one carries no registry token.
+        let client = make_client(matches.value_of("proxy"), None, request_timeout(&matches)?)?;
+        let audit_dir = AuditDir::new()?;
+        let manifest = tarball_manifest(&client, tarball, &audit_dir).await?;
+        Some((audit_dir, manifest))
+    } else if let Some(url) = matches.value_of("from-git") {
+        let audit_dir = AuditDir::new()?;
+        let manifest = git_manifest(url, &audit_dir)?;
+        Some((audit_dir, manifest))
+    } else {
+        None
+    };
+
+    let exit_code = if matches.is_present("workspaces") {
+        let root = match Path::new(path) {
+            dir if dir.is_dir() => dir,
+            file => project_dir(file.to_str().unwrap_or_default()),
+        };
+        let manifests = workspace::find_manifests(root, matches.value_of_t("workspace-depth")?);
+        let workspace = workspace::read_manifests(&manifests);
+        for warning in workspace::duplicate_majors(&workspace) {
+            println!("Warning: {}", warning);
+        }
+        if let Some(graph_path) = matches.value_of("graph") {
+            let graph = workspace::graph(&workspace);
+            let contents = if graph_path.ends_with(".dot") {
+                graph.to_dot()
+            } else {
+                serde_json::to_string_pretty(&graph)?
+            };
+            fs::write(graph_path, contents)?;
+        }
+
+        let parallel_files = matches.value_of_t("parallel-files")?;
+        let (exit_code, failed) = check_workspace(
+            &manifests,
+            root,
+            &matches,
+            &session,
+            &options,
+            should_update,
+            parallel_files,
+        )
+        .await;
+        if failed > 0 {
+            return Err(eyre!(
+                "{} of {} manifests could not be checked",
+                failed,
+                manifests.len()
+            ));
+        }
+        exit_code
+    } else if let Some((_audit_dir, manifest)) = audited {
+        check_manifest(
+            &manifest.to_string_lossy(),
+            &matches,
+            &session,
+            &options,
+            should_update,
+            false,
+        )
+        .await?
+    } else {
+        check_manifest(
+            path,
+            &matches,
+            &session,
+            &options,
+            should_update,
+            matches.is_present("catalog"),
+        )
+        .await?
+    };
+
+    // These modes print a listing meant for scripts, so leave it at that.
+    let listing_only = [
+        "validate",
+        "sort-preview",
+        "rewrite-protocol",
+        "names-only",
+        "repository-links-only",
+    ]
+    .iter()
+    .any(|flag| matches.is_present(flag));
+    if !json_output && !listing_only {
+        let end = Instant::now();
+        println!(
+            "Operation completed, duration: {:#.2?}",
+            end.duration_since(start)
+        );
+    }
+
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+
+    Ok(())
+}
+
+/// Checks the manifests of a workspace, at most `parallel` of them at once, returning the exit
+/// codes they call for OR-ed together and how many couldn't be checked.
+async fn check_workspace(
+    manifests: &[PathBuf],
+    root: &Path,
+    matches: &ArgMatches,
+    session: &Session,
+    options: &CheckOptions,
+    update: bool,
+    parallel: usize,
+) -> (i32, usize) {
+    let results: Vec<Result<i32, Error>> = stream::iter(manifests)
+        .map(|manifest| async move {
+            let manifest = manifest.to_string_lossy();
+            print_line(&format!("{}:", manifest), matches.is_present("json"));
+            // Catalogs live next to the workspace root only.
+            let check_catalog = matches.is_present("catalog") && project_dir(&manifest) == root;
+            check_manifest(&manifest, matches, session, options, update, check_catalog).await
+        })
+        .buffered(parallel.max(1))
+        .collect()
+        .await;
+
+    let mut exit_code = 0;
+    let mut failed = 0;
+    for result in results {
+        match result {
+            Ok(code) => exit_code |= code,
+            Err(err) => {
+                eprintln!("Error: {}", err);
+                failed += 1;
+            }
+        }
+    }
+    (exit_code, failed)
+}
+
+/// Whether the run writes updates: with the `update` subcommand, `-u`, or `-y`, which also
+/// installs them. `check` never writes, so it rejects both flags.
+fn update_requested(subcommand: Option<&str>, matches: &ArgMatches) -> Result<bool, Error> {
+    let should_update = subcommand == Some("update")
+        || matches.is_present("update")
+        || matches.is_present("assume-yes");
+    if subcommand == Some("check") && should_update {
+        return Err(eyre!(
+            "`check` never writes, use `update` instead of -u or -y"
+        ));
+    }
+
+    Ok(should_update)
+}
+
+/// Checks (and with `-u` updates) a single manifest, returning the exit code it calls for.
+async fn check_manifest(
+    path: &str,
+    matches: &ArgMatches,
+    session: &Session,
+    options: &CheckOptions,
+    should_update: bool,
+    check_catalog: bool,
+) -> Result<i32, Error> {
+    let assume_yes = should_update && matches.is_present("assume-yes");
+    let should_explain = matches.is_present("explain");
+    let verbose = matches.is_present("verbose");
+    let github_format = matches.value_of("format") == Some("github");
+    let json_output = matches.is_present("json");
+    let skip_majors = matches.is_present("no-update-if-same-major");
+    let write_peer = matches.is_present("write-peer");
+    let interactive = should_update && matches.is_present("interactive");
+
+    let original_file_contents = fs::read_to_string(path)?;
+    let (package_file_contents, has_bom) = strip_bom(&original_file_contents);
+    // Keep a byte order mark the manifest already had, unless told otherwise.
+    let write_bom = matches.is_present("bom") || (has_bom && !matches.is_present("no-bom"));
+    let stripped_contents = jsonc::strip_comments(package_file_contents);
+    let has_comments = stripped_contents != package_file_contents;
+    let mut package_json: serde_json::Value = serde_json::from_str(&stripped_contents)?;
+
+    if matches.is_present("validate") {
+        let problems = validate_manifest(&package_json);
+        if !problems.is_empty() {
+            return Err(eyre!(
+                "{} has malformed dependency specs:\n{}",
+                path,
+                problems.join("\n")
+            ));
+        }
+        println!("All dependency specs in {} are valid.", path);
+        return Ok(0);
+    }
+
+    if matches.is_present("sort-preview") {
+        let moves = sort_moves(&package_json);
+        if moves.is_empty() {
+            println!("Every dependency section of {} is already sorted.", path);
+        }
+        for line in moves {
+            println!("{}", line);
+        }
+        return Ok(0);
+    }
+
+    for warning in numeric_versions(&package_json) {
+        print_warning(&warning, json_output);
+    }
+
+    if let Some(rules) = matches.value_of("rewrite-protocol") {
+        let rules = parse_rewrite_rules(rules)?;
+        let mut sections = vec![];
+        let mut rewritten = 0;
+        for section in SECTION_ORDER {
+            let mut entries = dependency_section(&package_json, section)?;
+            for (package_name, spec) in entries.iter_mut() {
+                if let Some(new_spec) = rewrite_spec(spec, &rules) {
+                    println!("{}     {} => {}", package_name, spec, new_spec);
+                    *spec = new_spec;
+                    rewritten += 1;
+                }
+            }
+            sections.push((section, entries));
+        }
+
+        if should_update && rewritten > 0 {
+            let new_package_file_contents = if has_comments {
+                let sections: Vec<(&str, &IndexMap<String, String>)> = sections
+                    .iter()
+                    .map(|(section, entries)| (*section, entries))
+                    .collect();
+                edit_manifest_versions(package_file_contents, &package_json, &sections)?
+            } else {
+                for (section, entries) in sections {
+                    write_section(&mut package_json, section, entries)?;
+                }
+                serde_json::to_string_pretty(&package_json)?
+            };
+            write_manifest(path, &new_package_file_contents, write_bom)?;
+            println!("Rewrote {} dependency specs in {}.", rewritten, path);
+        } else if rewritten == 0 {
+            println!("No dependency specs to rewrite.");
+        }
+        return Ok(0);
+    }
+
+    let registry = Arc::new(session.registry_for(matches, path, &package_json));
+
+    if matches.is_present("precheck") {
+        registry.ping().await?;
+    }
+
+    let mut options = options.clone();
+    if let Some(policy_url) = matches.value_of("policy-url") {
+        options.allowed_versions = registry.version_policy(policy_url).await?;
+    }
+    let options = Arc::new(options);
+
+    let mut deps = dependency_section(&package_json, DEP_KEY)?;
+    let mut dev_deps = dependency_section(&package_json, DEV_DEP_KEY)?;
+    let mut peer_deps = dependency_section(&package_json, PEER_DEP_KEY)?;
+    let mut optional_deps = dependency_section(&package_json, OPTIONAL_DEP_KEY)?;
+    let mut resolutions = dependency_section(&package_json, RESOLUTIONS_KEY)?;
+
+    for warning in bundled_drift(&package_json, &deps, project_dir(path)) {
+        print_warning(&warning, json_output);
+    }
+    for warning in unknown_trusted_dependencies(
+        &package_json,
+        &[&deps, &dev_deps, &peer_deps, &optional_deps],
+    ) {
+        print_warning(&warning, json_output);
+    }
+
+    let catalog_path = Path::new(path).with_file_name(PNPM_WORKSPACE_FILE);
+    let catalog_file_contents = if check_catalog {
+        fs::read_to_string(&catalog_path)?
+    } else {
+        String::new()
+    };
+    let catalogs = parse_catalogs(&catalog_file_contents)?;
+
+    let dep_futures = process_dependencies(&registry, &options, &deps, DEP_KEY).await;
+    let dev_dep_futures = process_dependencies(&registry, &options, &dev_deps, DEV_DEP_KEY).await;
+    let peer_dep_futures =
+        process_dependencies(&registry, &options, &peer_deps, PEER_DEP_KEY).await;
+    let optional_dep_futures =
+        process_dependencies(&registry, &options, &optional_deps, OPTIONAL_DEP_KEY).await;
+    let resolution_futures =
+        process_dependencies(&registry, &options, &resolutions, RESOLUTIONS_KEY).await;
+    let mut catalog_futures = vec![];
+    for (catalog, entries) in &catalogs {
+        let futures = process_dependencies(&registry, &options, entries, CATALOG_KEY).await;
+        catalog_futures.push((catalog.clone(), futures));
+    }
+    // Packages left out by --filter and --reject are done straight away, but still counted.
+    let catalog_count: usize = catalog_futures
+        .iter()
+        .map(|(_, futures)| futures.len())
+        .sum();
+    let dep_count = (dep_futures.len()
+        + dev_dep_futures.len()
+        + peer_dep_futures.len()
+        + optional_dep_futures.len()
+        + resolution_futures.len()
+        + catalog_count) as u64;
+
+    let mut checks = vec![];
+    let mut pb = if matches.value_of("progress") == Some("json") {
+        Progress::Json {
+            done: 0,
+            total: dep_count,
+            out: Box::new(io::stderr()),
+        }
+    } else if json_output {
+        // Keep stdout for the JSON output alone.
+        Progress::Json {
+            done: 0,
+            total: dep_count,
+            out: Box::new(io::sink()),
+        }
+    } else {
+        let mut bar = ProgressBar::new(dep_count);
+        bar.show_speed = false;
+        bar.show_time_left = false;
+        Progress::Bar(Box::new(bar))
+    };
+
+    let mut warnings = vec![];
+    for futures in [
+        dep_futures,
+        dev_dep_futures,
+        peer_dep_futures,
+        optional_dep_futures,
+        resolution_futures,
+    ] {
+        warnings.extend(await_futures(futures, &mut pb, &mut checks).await?);
+    }
+
+    let mut catalog_checks = vec![];
+    for (catalog, futures) in catalog_futures {
+        let mut checks = vec![];
+        warnings.extend(await_futures(futures, &mut pb, &mut checks).await?);
+        catalog_checks.push((catalog, checks));
+    }
+    registry.save_cache()?;
+
+    // Printed only now, so they don't break up the progress bar.
+    for check in checks
+        .iter()
+        .chain(catalog_checks.iter().flat_map(|(_, checks)| checks))
+    {
+        if let PackageCheck::Failed {
+            package_name,
+            error,
+        } = check
+        {
+            let line = format!("Error when fetching {} version, {}", package_name, error);
+            print_line(&line, json_output);
+        }
+    }
+    for warning in warnings {
+        print_warning(&warning, json_output);
+    }
+
+    for republished in registry.republished() {
+        let warning = format!(
+            "{} {} was republished with different contents, its integrity changed from {} to {}",
+            republished.package_name,
+            republished.version,
+            republished.previous_integrity,
+            republished.integrity
+        );
+        print_warning(&warning, json_output);
+    }
+
+    if matches.is_present("include-deprecated-current") {
+        for warning in deprecated_current(
+            &registry,
+            &options,
+            &[&deps, &dev_deps, &peer_deps, &optional_deps],
+            json_output,
+        )
+        .await
+        {
+            print_warning(&warning, json_output);
+        }
+    }
+
+    if should_explain {
+        for check in &checks {
+            let line = format!("{}     {}", check.package_name(), check.explain(&options));
+            print_line(&line, json_output);
+        }
+        for (catalog, checks) in &catalog_checks {
+            for check in checks {
+                let line = format!(
+                    "{} ({})     {}",
+                    check.package_name(),
+                    catalog_label(catalog),
+                    check.explain(&options)
+                );
+                print_line(&line, json_output);
+            }
+        }
+    }
+
+    let exit_code = if matches.is_present("exit-bitmask") {
+        exit_bitmask(
+            checks
+                .iter()
+                .chain(catalog_checks.iter().flat_map(|(_, checks)| checks)),
+        )
+    } else {
+        0
+    };
+
+    let summary = summary_json(
+        checks
+            .iter()
+            .chain(catalog_checks.iter().flat_map(|(_, checks)| checks)),
+    );
+    if let Some(summary_path) = matches.value_of("summary-json") {
+        fs::write(summary_path, serde_json::to_string_pretty(&summary)?)?;
+    }
+
+    if matches.is_present("fail-if-deprecated") {
+        deprecation_gate(
+            checks
+                .iter()
+                .chain(catalog_checks.iter().flat_map(|(_, checks)| checks)),
+        )?;
+    }
+
+    if matches.is_present("names-only") {
+        println!(
+            "{}",
+            updatable_names(
+                checks
+                    .iter()
+                    .chain(catalog_checks.iter().flat_map(|(_, checks)| checks)),
+            )
+        );
+        return Ok(0);
+    }
+
+    if matches.is_present("repository-links-only") {
+        for (package_name, link) in repository_links(&registry, &checks).await {
+            println!(
+                "{}     {}",
+                package_name,
+                link.as_deref().unwrap_or("(no repository)")
+            );
+        }
+        return Ok(0);
+    }
+
+    if matches.is_present("compare-to-installed") {
+        let project_dir = project_dir(path);
+        for check in &checks {
+            let package_name = check.package_name();
+            let range = match deps
+                .get(package_name)
+                .or_else(|| dev_deps.get(package_name))
+                .or_else(|| peer_deps.get(package_name))
+                .or_else(|| optional_deps.get(package_name))
+                .or_else(|| resolutions.get(package_name))
+            {
+                Some(range) => range,
+                None => continue,
+            };
+
+            let line = format!(
+                "{}     {}     {}     {}",
+                package_name,
+                range,
+                installed_version(project_dir, package_name).unwrap_or_else(|| "-".to_string()),
+                check.latest(range).unwrap_or("-")
+            );
+            print_line(&line, json_output);
+        }
+    }
+
+    let release_notes = match matches.value_of("changelog-fetch") {
+        Some(count) => {
+            // A separate client, so the registry token is never sent to GitHub.
+            let client = make_client(matches.value_of("proxy"), None, request_timeout(matches)?)?;
+            fetch_release_notes(
+                &registry,
+                &client,
+                changelog::GITHUB_API_URL,
+                &checks,
+                count.parse()?,
+                json_output,
+            )
+            .await
+        }
+        None => vec![],
+    };
+
+    if let Some(min_downloads) = matches.value_of("min-downloads") {
+        // Like the changelog client, this one carries no registry token.
+        let client = make_client(matches.value_of("proxy"), None, request_timeout(matches)?)?;
+        let warnings = low_download_warnings(
+            &registry,
+            &client,
+            DOWNLOADS_API_URL,
+            &checks,
+            min_downloads.parse()?,
+            json_output,
+        )
+        .await;
+        for warning in warnings {
+            print_warning(&warning, json_output);
+        }
+    }
+
+    let group_by_registry = matches.is_present("group-by-registry");
+    if group_by_registry {
+        checks = group_updates_by_registry(checks);
+    }
+    let group_by_severity = matches.is_present("group-by-severity");
+    if group_by_severity {
+        checks = group_updates_by_bump(checks);
+    }
+    let bumps: Vec<Bump> = checks
+        .iter()
+        .filter_map(|check| match check {
+            PackageCheck::Update(update) => {
+                Some(classify_bump(&update.old_version, &update.new_version))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let failed_count = checks
+        .iter()
+        .chain(catalog_checks.iter().flat_map(|(_, checks)| checks))
+        .filter(|check| matches!(check, PackageCheck::Failed { .. }))
+        .count();
+
+    if json_output {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&updates_json(&checks, &catalog_checks))?
+        );
+    }
+
+    // Updates found, and the ones actually written: some are held back or declined at the prompt.
+    let mut update_count = 0;
+    let mut written_count = 0;
+    let mut heading = None;
+    for check in checks {
+        let update = match check {
+            PackageCheck::Update(update) => update,
+            _ => continue,
+        };
+
+        update_count += 1;
+        registry.stats.record_update(&update.registry);
+        // With --json the updates were already printed together above.
+        if !json_output {
+            if group_by_registry {
+                let host = registry_host(&update.registry);
+                if heading.as_ref() != Some(&host) {
+                    println!("{}:", host);
+                    heading = Some(host);
+                }
+            }
+            if group_by_severity {
+                let bump = classify_bump(&update.old_version, &update.new_version);
+                let count = bumps.iter().filter(|other| **other == bump).count();
+                let bump_heading = severity_heading(bump, count);
+                if heading.as_ref() != Some(&bump_heading) {
+                    println!("{}", bump_heading);
+                    heading = Some(bump_heading);
+                }
+            }
+            // What `npm update` would install without touching the range, next to the latest.
+            let mut wanted = if options.wanted {
+                format!(
+                    "     wanted: {}",
+                    update.wanted.as_deref().unwrap_or("none")
+                )
+            } else {
+                String::new()
+            };
+            for (target, version) in &update.compared {
+                wanted.push_str(&format!(
+                    "     {}: {}",
+                    target,
+                    version.as_deref().unwrap_or("none")
+                ));
+            }
+            if github_format {
+                println!("{}", github_annotation(&update));
+            } else if verbose {
+                println!(
+                    "{}     {} => {}{} (from {})",
+                    update.package_name,
+                    update.old_version,
+                    update.new_version,
+                    wanted,
+                    update.registry
+                );
+            } else {
+                println!(
+                    "{}     {} => {}{}",
+                    update.package_name, update.old_version, update.new_version, wanted
+                );
+            }
+            if !update.since.is_empty() {
+                println!(
+                    "    published since {}: {}",
+                    update.old_version,
+                    update.since.join(", ")
+                );
+            }
+        }
+
+        if let Some(reason) = should_update
+            .then(|| held_back_reason(&update, skip_majors, write_peer))
+            .flatten()
+        {
+            if !json_output {
+                println!("    not writing {}: {}", update.package_name, reason);
+            }
+            continue;
+        }
+
+        if interactive && !confirm_update(&mut io::stdin().lock(), &mut io::stdout(), &update)? {
+            continue;
+        }
+
+        // If we should update the package.json file, update the relevant map.
+        if should_update {
+            written_count += 1;
+            match update.section {
+                DEV_DEP_KEY => dev_deps.insert(update.package_name, update.new_version),
+                PEER_DEP_KEY => peer_deps.insert(update.package_name, update.new_version),
+                OPTIONAL_DEP_KEY => optional_deps.insert(update.package_name, update.new_version),
+                RESOLUTIONS_KEY => resolutions.insert(update.package_name, update.new_version),
+                _ => deps.insert(update.package_name, update.new_version),
+            };
+        }
+    }
+
+    for (package_name, version, notes) in release_notes {
+        print_line(
+            &format!("Release notes for {} {}:", package_name, version),
+            json_output,
+        );
+        for line in notes.lines() {
+            print_line(&format!("    {}", line), json_output);
+        }
+    }
+
+    let mut new_catalog_file_contents = catalog_file_contents.clone();
+    for (catalog, checks) in catalog_checks {
+        for check in checks {
+            let update = match check {
+                PackageCheck::Update(update) => update,
+                _ => continue,
+            };
+
+            update_count += 1;
+            if !json_output {
+                println!(
+                    "{} ({})     {} => {}",
+                    update.package_name,
+                    catalog_label(&catalog),
+                    update.old_version,
+                    update.new_version
+                );
+            }
+
+            // Catalog entries are held back for the same reasons as the manifest's own.
+            if let Some(reason) = should_update
+                .then(|| held_back_reason(&update, skip_majors, write_peer))
+                .flatten()
+            {
+                if !json_output {
+                    println!("    not writing {}: {}", update.package_name, reason);
+                }
+                continue;
+            }
+
+            if interactive && !confirm_update(&mut io::stdin().lock(), &mut io::stdout(), &update)?
+            {
+                continue;
+            }
+
+            if should_update {
+                written_count += 1;
+            }
+            new_catalog_file_contents = update_catalog_yaml(
+                &new_catalog_file_contents,
+                catalog.as_deref(),
+                &update.package_name,
+                &update.new_version,
+            );
+        }
+    }
+
+    let catalog_written = should_update && new_catalog_file_contents != catalog_file_contents;
+    if catalog_written {
+        fs::write(&catalog_path, new_catalog_file_contents)?;
+        if !json_output {
+            println!("Updated catalogs in {}.", catalog_path.display());
+        }
+    }
+
+    // Finally, merge the newly updated versions into the previous value struct.
+    if should_update {
+        // Manifests with comments are edited in place so the comments and formatting survive.
+        let new_package_file_contents = if has_comments {
+            edit_manifest_versions(
+                package_file_contents,
+                &package_json,
+                &[
+                    (DEP_KEY, &deps),
+                    (DEV_DEP_KEY, &dev_deps),
+                    (PEER_DEP_KEY, &peer_deps),
+                    (OPTIONAL_DEP_KEY, &optional_deps),
+                    (RESOLUTIONS_KEY, &resolutions),
+                ],
+            )?
+        } else {
+            insert_new_maps(&mut package_json, deps, dev_deps, peer_deps, optional_deps)?;
+            write_section(&mut package_json, RESOLUTIONS_KEY, resolutions)?;
+            serde_json::to_string_pretty(&package_json)?
+        };
+
+        if matches.is_present("backup") {
+            let backup_path = write_backup(path, &original_file_contents)?;
+            if !json_output {
+                println!("Saved the original manifest to {}.", backup_path);
+            }
+        }
+
+        // Write the updated package.json file.
+        write_manifest(path, &new_package_file_contents, write_bom)?;
+
+        if matches.is_present("verify-after-write") {
+            verify_written_manifest(path)?;
+        }
+
+        if written_count > 0 {
+            let install_command = (matches.is_present("install") || assume_yes).then(|| {
+                matches
+                    .value_of("install-command")
+                    .map(String::from)
+                    .unwrap_or_else(|| {
+                        install_command(
+                            &package_json,
+                            project_dir(path),
+                            matches.is_present("ignore-scripts"),
+                        )
+                    })
+            });
+            let mut originals = vec![(Path::new(path), original_file_contents.as_str())];
+            if catalog_written {
+                originals.push((catalog_path.as_path(), catalog_file_contents.as_str()));
+            }
+            run_post_write_commands(
+                path,
+                &originals,
+                install_command.as_deref(),
+                matches.value_of("test-command"),
+            )?;
+        }
+
+        if json_output {
+            // Keep stdout valid JSON.
+        } else if written_count > 0 {
+            let display_path = if matches.is_present("absolute-path") {
+                fs::canonicalize(path)?.display().to_string()
+            } else {
+                path.to_string()
+            };
+            println!(
+                "{}",
+                render_message(
+                    matches
+                        .value_of("message")
+                        .unwrap_or(DEFAULT_UPDATED_MESSAGE),
+                    &display_path
+                )
+            );
+            if !matches.is_present("install") && !assume_yes {
+                println!(
+                    "Run `{}` to update the lockfile.",
+                    lockfile_command(&package_json, project_dir(path))
+                );
+            }
+        } else if update_count > 0 {
+            println!("No dependency updates written.");
+        } else {
+            println!("No dependency updates found.");
+        }
+    }
+
+    let reported_count = if should_update {
+        written_count
+    } else {
+        update_count
+    };
+    if let Some(status) = partial_failure_status(reported_count, failed_count, should_update) {
+        if json_output {
+            eprintln!("{}", status);
+        } else {
+            println!("{}", status);
+        }
+    }
+
+    if assume_yes {
+        // With --json the report already is the one JSON document on stdout.
+        print_line(&summary.to_string(), json_output);
+        if failed_count > 0 {
+            return Err(eyre!("{} packages failed to check", failed_count));
+        }
+    }
+
+    if verbose {
+        let mut lines = vec![
+            format!(
+                "Registry requests: {}, downloaded {} bytes",
+                registry.stats.requests.load(Ordering::Relaxed),
+                registry.stats.bytes.load(Ordering::Relaxed)
+            ),
+            "Slowest packages:".to_string(),
+        ];
+        for (package_name, duration) in registry.stats.slowest(SLOWEST_FETCHES) {
+            lines.push(format!("    {}     {:#.2?}", package_name, duration));
+        }
+        // Kept off stdout under `--json`, which must stay valid JSON.
+        for line in lines {
+            if json_output {
+                eprintln!("{}", line);
+            } else {
+                println!("{}", line);
+            }
+        }
+    }
+
+    if matches.is_present("aggregate-by-registry") {
+        for (host, tally) in registry.stats.registry_tallies() {
+            let line = format!(
+                "{}: {} packages, {} updates, {} auth failures",
+                host, tally.packages, tally.updates, tally.auth_failures
+            );
+            if json_output {
+                eprintln!("{}", line);
+            } else {
+                println!("{}", line);
+            }
+        }
+    }
+
+    Ok(if matches.is_present("fail-on-update") && exit_code == 0 {
+        fail_on_update_code(update_count, written_count)
+    } else {
+        exit_code
+    })
+}
+
+/// What every manifest checked in a run shares: one registry, so manifests checked side by
+/// side stay within its concurrency limit, share its cache and don't fetch a package twice.
+struct Session {
+    registry: Arc<Registry>,
+}
+
+impl Session {
+    fn new(matches: &ArgMatches) -> Result<Self, Error> {
+        Ok(Session {
+            registry: Arc::new(build_registry(matches)?),
+        })
+    }
+
+    /// The shared registry, querying what the manifest at `path` asks for: its `publishConfig`
+    /// registry and, with `--registry-from-lockfile`, where the lockfile next to it installed
+    /// packages from.
+    fn registry_for(&self, matches: &ArgMatches, path: &str, package_json: &Value) -> Registry {
+        let registry = self.registry.for_manifest(resolve_registry(
+            package_json,
+            configured_registry(matches).as_deref(),
+        ));
+        if !matches.is_present("registry-from-lockfile") {
+            return registry;
+        }
+
+        let lockfile = fs::read_to_string(project_dir(path).join(NPM_LOCKFILE))
+            .ok()
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or(Value::Null);
+        registry.with_package_registries(lockfile_registries(&lockfile))
+    }
+}
+
+/// The registry given with `--registry` or the environment, which overrides `publishConfig`.
+fn configured_registry(matches: &ArgMatches) -> Option<String> {
+    matches
+        .value_of("registry")
+        .map(String::from)
+        .or_else(|| env::var(REGISTRY_ENV_VAR).ok())
+}
+
+/// The `--timeout` for each request, in seconds.
+fn request_timeout(matches: &ArgMatches) -> Result<Duration, Error> {
+    Ok(matches
+        .value_of("timeout")
+        .map(str::parse)
+        .transpose()?
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_TIMEOUT))
+}
+
+/// Sets up the registry from the command line options, with the on-disk cache unless
+/// `--no-cache` is passed. Manifests that name a registry of their own query it through
+/// `Session::registry_for`.
+fn build_registry(matches: &ArgMatches) -> Result<Registry, Error> {
+    let token = env::var(TOKEN_ENV_VAR).ok();
+    let client = make_client(
+        matches.value_of("proxy"),
+        token.as_deref(),
+        request_timeout(matches)?,
+    )?;
+    let search_registries = matches
+        .value_of("search-registries")
+        .map(|urls| urls.split(',').map(|url| url.trim().to_string()).collect())
+        .unwrap_or_default();
+    let mut registry = Registry::new(
+        client,
+        resolve_registry(&Value::Null, configured_registry(matches).as_deref()),
+        matches.value_of("mirror").map(String::from),
+    )
+    .with_search_registries(search_registries)
+    .with_latest_from_packument(matches.value_of("latest-source") == Some("packument"))
+    .with_concurrency(
+        matches
+            .value_of("concurrency")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(DEFAULT_CONCURRENCY),
+    )
+    .with_retries(
+        matches
+            .value_of("retries")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(DEFAULT_RETRIES),
+    );
+    if let Some(start) = matches.value_of("concurrency-ramp") {
+        registry = registry.with_concurrency_ramp(start.parse()?);
+    }
+    if let Some(budget) = matches.value_of("retry-budget") {
+        registry = registry.with_retry_budget(Arc::new(AtomicU32::new(budget.parse()?)));
+    }
+    if !matches.is_present("no-cache") {
+        let ttl_minutes = matches
+            .value_of("cache-ttl")
+            .map(str::parse)
+            .transpose()?
+            .unwrap_or(cache::DEFAULT_TTL_MINUTES);
+        let package_ttls = matches
+            .values_of("max-age-cache-per-package")
+            .unwrap_or_default()
+            .map(parse_package_ttl)
+            .collect::<Result<_, _>>()?;
+        registry = registry.with_cache(
+            Cache::load(cache::default_path(), Duration::from_secs(ttl_minutes * 60))
+                .with_package_ttls(package_ttls),
+        );
+    }
+    Ok(registry)
+}
+
+/// Prints the latest version of a package, for `query`.
+async fn query_package(matches: &ArgMatches, session: &Session) -> Result<(), Error> {
+    let package_name = matches.value_of("package").unwrap_or_default();
+    let registry = &session.registry;
+    let latest = registry.latest_version(package_name).await?;
+    registry.save_cache()?;
+
+    println!(
+        "{} {} (from {})",
+        package_name, latest.value.version, latest.registry
+    );
+    if let Some(message) = latest.value.deprecation() {
+        println!("    deprecated: {}", message);
+    }
+    Ok(())
+}
+
+/// Diagnoses what checking `path` relies on, for `doctor`: that the manifest parses and its specs
+/// are valid and that the registries answer, along with where the cache lives and how installs
+/// would run. Fails if any problem was found.
+async fn doctor(path: &str, matches: &ArgMatches, session: &Session) -> Result<(), Error> {
+    let mut problems = 0;
+
+    let parsed = fs::read_to_string(path)
+        .map_err(Error::from)
+        .and_then(|contents| {
+            let contents = strip_bom(&contents).0;
+            Ok(serde_json::from_str::<Value>(&jsonc::strip_comments(
+                contents,
+            ))?)
+        });
+    let package_json = match parsed {
+        Ok(package_json) => {
+            println!("ok       {} parses", path);
+            package_json
+        }
+        Err(err) => {
+            println!("problem  {} can't be read: {}", path, err);
+            problems += 1;
+            Value::Null
+        }
+    };
+    let invalid = validate_manifest(&package_json);
+    if invalid.is_empty() && !package_json.is_null() {
+        println!("ok       every dependency spec is well formed");
+    }
+    for problem in &invalid {
+        println!("problem  {}", problem);
+    }
+    problems += invalid.len();
+
+    let registry = session.registry_for(matches, path, &package_json);
+    match registry.ping().await {
+        Ok(()) => println!("ok       the registries are reachable"),
+        Err(err) => {
+            println!("problem  {}", err);
+            problems += 1;
+        }
+    }
+
+    let token = if env::var_os(TOKEN_ENV_VAR).is_some() {
+        "set"
+    } else {
+        "not set"
+    };
+    println!("info     {} is {}", TOKEN_ENV_VAR, token);
+    println!(
+        "info     the registry cache is at {}",
+        cache::default_path().display()
+    );
+    println!(
+        "info     --install would run `{}`",
+        install_command(
+            &package_json,
+            project_dir(path),
+            matches.is_present("ignore-scripts")
+        )
+    );
+
+    if problems > 0 {
+        return Err(eyre!("doctor found {} problems", problems));
+    }
+    Ok(())
+}
+
+/// The `--fail-on-update` exit code: `UPDATES_AVAILABLE_EXIT_CODE` when some updates found were
+/// not written, held back or not, so CI can fail on stale dependencies, and 0 otherwise.
+fn fail_on_update_code(updates: usize, written: usize) -> i32 {
+    if updates > written {
+        UPDATES_AVAILABLE_EXIT_CODE
+    } else {
+        0
+    }
+}
+
+/// The names of every updatable package separated by spaces, for `--names-only`.
+fn updatable_names<'a>(checks: impl Iterator<Item = &'a PackageCheck>) -> String {
+    let names: Vec<&str> = checks
+        .filter_map(|check| match check {
+            PackageCheck::Update(update) => Some(update.package_name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    names.join(" ")
+}
+
+/// Looks up the source repository of every outdated package, for `--repository-links-only`,
+/// within the registry's concurrency limit.
+async fn repository_links(
+    registry: &Registry,
+    checks: &[PackageCheck],
+) -> Vec<(String, Option<String>)> {
+    let updates: Vec<&PackageUpdateData> = checks
+        .iter()
+        .filter_map(|check| match check {
+            PackageCheck::Update(update) => Some(update),
+            _ => None,
+        })
+        .collect();
+    let packuments = join_all(updates.iter().map(|update| async move {
+        let _permit = registry.acquire().await;
+        registry.packument(&update.query_name).await
+    }))
+    .await;
+
+    updates
+        .into_iter()
+        .zip(packuments)
+        .map(|(update, packument)| {
+            let link = packument
+                .ok()
+                .and_then(|packument| packument.value.repository_url());
+            (update.package_name.clone(), link)
+        })
+        .collect()
+}
+
+/// The `--exit-bitmask` exit code: bit 1 is set for patch updates, 2 for minor and 4 for major
+/// (or unclassifiable) ones.
+fn exit_bitmask<'a>(checks: impl Iterator<Item = &'a PackageCheck>) -> i32 {
+    checks
+        .filter_map(|check| match check {
+            PackageCheck::Update(update) => Some(
+                match classify_bump(&update.old_version, &update.new_version) {
+                    Bump::Patch => 1,
+                    Bump::Minor => 2,
+                    Bump::Major | Bump::Other => 4,
+                },
+            ),
+            _ => None,
+        })
+        .fold(0, |code, bit| code | bit)
+}
+
+/// Reorders checks so that updates from the same registry sit together, keeping registries in
+/// the order they were first seen, for `--group-by-registry`.
+fn group_updates_by_registry(checks: Vec<PackageCheck>) -> Vec<PackageCheck> {
+    let mut groups: IndexMap<String, Vec<PackageCheck>> = IndexMap::new();
+    for check in checks {
+        let host = match &check {
+            PackageCheck::Update(update) => registry_host(&update.registry),
+            _ => String::new(),
+        };
+        groups.entry(host).or_default().push(check);
+    }
+
+    groups.into_values().flatten().collect()
+}
+
+/// Reorders checks so that updates sit together by how far they move, majors first and updates
+/// that can't be classified last, for `--group-by-severity`.
+fn group_updates_by_bump(mut checks: Vec<PackageCheck>) -> Vec<PackageCheck> {
+    checks.sort_by_key(|check| match check {
+        PackageCheck::Update(update) => {
+            Some(classify_bump(&update.old_version, &update.new_version))
+        }
+        _ => None,
+    });
+    checks
+}
+
+/// The heading printed above a `--group-by-severity` group, such as `Major (2):`.
+fn severity_heading(bump: Bump, count: usize) -> String {
+    let label = bump.to_string();
+    format!("{}{} ({}):", label[..1].to_uppercase(), &label[1..], count)
+}
+
+/// Fetches release note excerpts from GitHub for the first `count` updates, one request at a time
+/// to stay clear of the API's rate limits. Only their repositories are looked up, each lookup
+/// within the registry's concurrency limit. Updates without a GitHub repository or a matching
+/// release are left out.
+async fn fetch_release_notes(
+    registry: &Registry,
+    client: &Client,
+    api_url: &str,
+    checks: &[PackageCheck],
+    count: usize,
+    json_output: bool,
+) -> Vec<(String, String, String)> {
+    let updates = checks.iter().filter_map(|check| match check {
+        PackageCheck::Update(update) => Some(update),
+        _ => None,
+    });
+
+    let mut notes = vec![];
+    for update in updates.take(count) {
+        let _permit = registry.acquire().await;
+        let link = registry
+            .packument(&update.query_name)
+            .await
+            .ok()
+            .and_then(|packument| packument.value.repository_url());
+        let (owner, repo) = match link.as_deref().and_then(changelog::github_repo) {
+            Some(repo) => repo,
+            None => continue,
+        };
+        let version = update.new_version.trim_start_matches(['^', '~', '>', '=']);
+        match changelog::release_excerpt(client, api_url, &owner, &repo, version).await {
+            Ok(Some(excerpt)) => {
+                notes.push((update.package_name.clone(), version.to_string(), excerpt))
+            }
+            Ok(None) => {}
+            Err(err) => print_line(
+                &format!(
+                    "Error when fetching release notes for {}, {}",
+                    update.package_name, err
+                ),
+                json_output,
+            ),
+        }
+    }
+
+    notes
+}
+
+/// Looks up the currently declared version of every dependency, describing those that have been
+/// deprecated: they need migrating away from even when there is nothing newer to update to.
+async fn deprecated_current(
+    registry: &Registry,
+    options: &CheckOptions,
+    sections: &[&IndexMap<String, String>],
+    json_output: bool,
+) -> Vec<String> {
+    let pinned: Vec<(&str, &str)> = sections
+        .iter()
+        .flat_map(|deps| deps.iter())
+        .filter(|(package_name, _)| options.is_checked(package_name))
+        .filter_map(|(package_name, spec)| Some((package_name.as_str(), pinned_version(spec)?)))
+        .collect();
+    let manifests = join_all(pinned.iter().map(|(package_name, version)| async move {
+        let _permit = registry.acquire().await;
+        registry.version_manifest(package_name, version).await
+    }))
+    .await;
+
+    let mut deprecated = vec![];
+    for ((package_name, version), manifest) in pinned.into_iter().zip(manifests) {
+        match manifest {
+            Ok(manifest) => {
+                if let Some(message) = manifest.value.deprecation() {
+                    deprecated.push(format!(
+                        "{}@{} is deprecated: {}",
+                        package_name, version, message
+                    ));
+                }
+            }
+            Err(err) => print_line(
+                &format!("Error when fetching {}@{}, {}", package_name, version, err),
+                json_output,
+            ),
+        }
+    }
+
+    deprecated
+}
+
+/// The exact version a spec is pinned to or floored at (`^1.2.3` is `1.2.3`), if it has one.
+fn pinned_version(spec: &str) -> Option<&str> {
+    if let Some((_, floor)) = comparison_floor(spec) {
+        return Some(floor);
+    }
+    if unsupported_spec(spec).is_some() {
+        return None;
+    }
+
+    let version = spec.trim().trim_start_matches(['^', '~', '=', 'v']);
+    Version::parse(version).ok().map(|_| version)
+}
+
+/// Looks up the weekly downloads of every updatable package, warning about those below
+/// `min_downloads`: rarely used packages deserve a closer look before upgrading. The lookups
+/// count towards the registry's concurrency limit.
+async fn low_download_warnings(
+    registry: &Registry,
+    client: &Client,
+    api_url: &str,
+    checks: &[PackageCheck],
+    min_downloads: u64,
+    json_output: bool,
+) -> Vec<String> {
+    let updates: Vec<&PackageUpdateData> = checks
+        .iter()
+        .filter_map(|check| match check {
+            PackageCheck::Update(update) => Some(update),
+            _ => None,
+        })
+        .collect();
+    let counts = join_all(updates.iter().map(|update| async move {
+        let _permit = registry.acquire().await;
+        weekly_downloads(client, api_url, &update.query_name).await
+    }))
+    .await;
+
+    let mut warnings = vec![];
+    for (update, count) in updates.into_iter().zip(counts) {
+        match count {
+            Ok(count) if count < min_downloads => warnings.push(format!(
+                "{} has only {} weekly downloads (fewer than {})",
+                update.package_name, count, min_downloads
+            )),
+            Ok(_) => {}
+            Err(err) => print_line(
+                &format!(
+                    "Error when fetching downloads of {}, {}",
+                    update.package_name, err
+                ),
+                json_output,
+            ),
+        }
+    }
+
+    warnings
+}
+
+/// The final status line of a run in which some packages failed to check, so that it isn't
+/// mistaken for a fully successful one.
+fn partial_failure_status(updates: usize, failed: usize, written: bool) -> Option<String> {
+    if failed == 0 {
+        return None;
+    }
+
+    let plural = |count: usize| if count == 1 { "" } else { "s" };
+    Some(format!(
+        "{} {} package{}; {} failed to check (see above).",
+        if written {
+            "Updated"
+        } else {
+            "Found updates for"
+        },
+        updates,
+        plural(updates),
+        failed
+    ))
+}
+
+/// The outcome of every check, for `--json`: the updatable packages with their current and latest
+/// version (and the `wanted` one with `--diff-only`) under `updates`, the failed lookups under
+/// `errors`, and everything else under `skipped` with the reason, including the packages left
+/// out by `--filter` and `--reject`. Every array is there even when it is empty, so the output
+/// always has the same shape.
+fn check_outcomes(checks: &[PackageCheck]) -> Value {
+    let (mut updates, mut errors, mut skipped) = (vec![], vec![], vec![]);
+    for check in checks {
+        match check {
+            PackageCheck::Update(update) => {
+                let mut entry = serde_json::json!({
+                    "name": update.package_name,
+                    "current": update.old_version,
+                    "latest": update.new_version,
+                });
+                if let Some(wanted) = &update.wanted {
+                    entry["wanted"] = Value::String(wanted.clone());
+                }
+                if !update.compared.is_empty() {
+                    entry["targets"] = update
+                        .compared
+                        .iter()
+                        .map(|(target, version)| (target.to_string(), serde_json::json!(version)))
+                        .collect::<serde_json::Map<_, _>>()
+                        .into();
+                }
+                updates.push(entry);
+            }
+            PackageCheck::Failed {
+                package_name,
+                error,
+            } => errors.push(serde_json::json!({ "name": package_name, "error": error })),
+            PackageCheck::Skipped {
+                package_name,
+                reason,
+            } => skipped.push(serde_json::json!({ "name": package_name, "reason": reason.code() })),
+        }
+    }
+    serde_json::json!({ "updates": updates, "errors": errors, "skipped": skipped })
+}
+
+/// The `--json` report: the outcome of the manifest's own checks, and under `catalogs` that of
+/// each pnpm catalog checked with `--catalog`, keyed by name (`default` for the unnamed one).
+fn updates_json(
+    checks: &[PackageCheck],
+    catalog_checks: &[(Option<String>, Vec<PackageCheck>)],
+) -> Value {
+    let mut report = check_outcomes(checks);
+    report["catalogs"] = catalog_checks
+        .iter()
+        .map(|(catalog, checks)| {
+            let name = catalog.as_deref().unwrap_or("default").to_string();
+            (name, check_outcomes(checks))
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into();
+    report
+}
+
+/// Counts the outcome of every check, for the `--summary-json` sidecar file.
+fn summary_json<'a>(checks: impl Iterator<Item = &'a PackageCheck>) -> Value {
+    let (mut checked, mut updates, mut skipped, mut failed) = (0, 0, 0, 0);
+    let mut bumps: IndexMap<String, usize> = [Bump::Major, Bump::Minor, Bump::Patch, Bump::Other]
+        .iter()
+        .map(|bump| (bump.to_string(), 0))
+        .collect();
+
+    for check in checks {
+        // Packages left out by --filter and --reject were never checked.
+        if matches!(
+            check,
+            PackageCheck::Skipped {
+                reason: SkipReason::Rejected,
+                ..
+            }
+        ) {
+            continue;
+        }
+        checked += 1;
+        match check {
+            PackageCheck::Update(update) => {
+                updates += 1;
+                *bumps
+                    .entry(classify_bump(&update.old_version, &update.new_version).to_string())
+                    .or_default() += 1;
+            }
+            PackageCheck::Skipped { .. } => skipped += 1,
+            PackageCheck::Failed { .. } => failed += 1,
+        }
+    }
+
+    serde_json::json!({
+        "checked": checked,
+        "updates": updates,
+        "skipped": skipped,
+        "failed": failed,
+        "bumps": bumps,
+    })
+}
+
+/// Fails with a listing of every update whose target version has been deprecated.
+fn deprecation_gate<'a>(checks: impl Iterator<Item = &'a PackageCheck>) -> Result<(), Error> {
+    let deprecated: Vec<String> = checks
+        .filter_map(|check| match check {
+            PackageCheck::Update(update) => update.deprecated.as_ref().map(|message| {
+                format!(
+                    "{}@{}: {}",
+                    update.package_name, update.new_version, message
+                )
+            }),
+            _ => None,
+        })
+        .collect();
+
+    if deprecated.is_empty() {
+        Ok(())
+    } else {
+        Err(eyre!(
+            "Target versions are deprecated:\n{}",
+            deprecated.join("\n")
+        ))
+    }
+}
+
+/// Helper function to await all dep futures and update the progress bar as each one finishes.
+/// The checks are collected in the order the futures were spawned, which is manifest order, no
+/// matter which lookups finish first, so output and writes are the same from run to run.
+async fn await_futures(
+    futures: Vec<JoinHandle<(PackageCheck, Vec<String>)>>,
+    progress: &mut Progress,
+    checks_vec: &mut Vec<PackageCheck>,
+) -> Result<Vec<String>, Error> {
+    let mut finished: Vec<_> = futures.iter().map(|_| None).collect();
+    let mut pending: FuturesUnordered<_> = futures
+        .into_iter()
+        .enumerate()
+        .map(|(index, future)| async move { (index, future.await) })
+        .collect();
+    while let Some((index, result)) = pending.next().await {
+        let (check, check_warnings) = result?;
+        if let Progress::Bar(bar) = progress {
+            bar.inc();
+        }
+        if let Progress::Json { done, total, out } = progress {
+            *done += 1;
+            let event = serde_json::json!({
+                "done": done,
+                "total": total,
+                "package": check.package_name(),
+            });
+            writeln!(out, "{}", event)?;
+        }
+        finished[index] = Some((check, check_warnings));
+    }
+
+    let mut warnings = vec![];
+    for (check, check_warnings) in finished.into_iter().flatten() {
+        warnings.extend(check_warnings);
+        checks_vec.push(check);
+    }
+    Ok(warnings)
+}
+
+/// Processes all dependencies in the given map. Returns a Vec containing a JoinHandle to the task
+/// for each dependency.
+async fn process_dependencies(
+    registry: &Arc<Registry>,
+    options: &Arc<CheckOptions>,
+    deps: &IndexMap<String, String>,
+    section: &'static str,
+) -> Vec<JoinHandle<(PackageCheck, Vec<String>)>> {
+    if options.skipped_sections.contains(&section) {
+        return vec![];
+    }
+
+    let futures: Vec<_> = deps
+        .iter()
+        .map(
+            |(package_name, version)| -> JoinHandle<(PackageCheck, Vec<String>)> {
+                // Kept as a skipped check, so --explain and --json can say why it is missing.
+                if !options.is_checked(package_name) {
+                    let check = PackageCheck::Skipped {
+                        package_name: package_name.clone(),
+                        reason: SkipReason::Rejected,
+                    };
+                    return tokio::spawn(async move { (check, vec![]) });
+                }
+                let registry = registry.clone();
+                let check = compare_package_version(
+                    registry.clone(),
+                    options.clone(),
+                    package_name.clone(),
+                    version.clone(),
+                    section,
+                );
+                tokio::spawn(async move {
+                    let _permit = registry.acquire().await;
+                    let start = Instant::now();
+                    let (check, warnings) = check.await;
+                    registry
+                        .stats
+                        .record_duration(check.package_name(), start.elapsed());
+                    (check, warnings)
+                })
+            },
+        )
+        .collect();
+
+    futures
+}
+
+/// Checks a single dependency against the registry, deciding whether it should be updated.
+/// `npm:` aliases are checked against the package they point at, and keep the alias form.
+/// Warnings about the package are returned alongside, to be shown once checking is done.
+async fn compare_package_version(
+    registry: Arc<Registry>,
+    options: Arc<CheckOptions>,
+    package_name: String,
+    version: String,
+    section: &'static str,
+) -> (PackageCheck, Vec<String>) {
+    let mut warnings = vec![];
+    if let Some((alias_target, range)) = npm_alias(&version) {
+        let check = check_version(
+            registry,
+            options,
+            package_name,
+            alias_target.to_string(),
+            range.to_string(),
+            section,
+            &mut warnings,
+        )
+        .await;
+
+        let check = match check {
+            PackageCheck::Update(mut update) => {
+                update.new_version = format!("npm:{}@{}", alias_target, update.new_version);
+                update.old_version = version;
+                PackageCheck::Update(update)
+            }
+            check => check,
+        };
+        return (check, warnings);
+    }
+
+    let query_name = if section == RESOLUTIONS_KEY {
+        resolution_package_name(&package_name).to_string()
+    } else {
+        package_name.clone()
+    };
+    let check = check_version(
+        registry,
+        options,
+        package_name,
+        query_name,
+        version,
+        section,
+        &mut warnings,
+    )
+    .await;
+    (check, warnings)
+}
+
+/// Splits an `npm:name@range` alias into the aliased package name and its range.
+fn npm_alias(spec: &str) -> Option<(&str, &str)> {
+    let (name, range) = spec.strip_prefix("npm:")?.rsplit_once('@')?;
+    (!name.is_empty()).then_some((name, range))
+}
+
+/// Checks the `version` spec of a dependency against the latest version of `query_name`.
+async fn check_version(
+    registry: Arc<Registry>,
+    options: Arc<CheckOptions>,
+    package_name: String,
+    query_name: String,
+    version: String,
+    section: &'static str,
+    warnings: &mut Vec<String>,
+) -> PackageCheck {
+    if let Some(reason) = unsupported_spec(&version) {
+        return PackageCheck::Skipped {
+            package_name,
+            reason,
+        };
+    }
+
+    let (ver_prefix, cmp_ver) = match comparison_floor(&version) {
+        Some(_) if options.leave_range_operators => {
+            return PackageCheck::Skipped {
+                package_name,
+                reason: SkipReason::RangeOperator,
+            }
+        }
+        Some((operator, floor)) => (operator, floor.to_string()),
+        None if version.contains('^') => ("^", version.replace(['^', '~'], "")),
+        None if version.contains('~') => ("~", version.replace(['^', '~'], "")),
+        None => ("", version.replace(['^', '~'], "")),
+    };
+    let cmp_ver = if options.loose {
+        coerce_version(&cmp_ver).map_or(cmp_ver, |version| version.to_string())
+    } else {
+        cmp_ver
+    };
+
+    if options.min_satisfying {
+        return match registry.versions(&query_name).await {
+            Ok(versions) => match min_satisfying_version(&version, &versions.value) {
+                Some(nearest) => PackageCheck::Update(PackageUpdateData {
+                    package_name,
+                    query_name,
+                    old_version: version,
+                    new_version: format!("{}{}", ver_prefix, nearest),
+                    section,
+                    registry: versions.registry,
+                    since: vec![],
+                    wanted: None,
+                    compared: vec![],
+                    deprecated: None,
+                }),
+                None => PackageCheck::Skipped {
+                    package_name,
+                    reason: SkipReason::RangeSatisfiable,
+                },
+            },
+            Err(err) => fetch_failed(package_name, err),
+        };
+    }
+
+    // Compound ranges only make sense to `--min-satisfying`, anything else would flatten them.
+    if version.contains("||") || version.trim().contains(char::is_whitespace) {
+        return PackageCheck::Skipped {
+            package_name,
+            reason: SkipReason::UnsupportedSpec,
+        };
+    }
+
+    let target = options.target_for(section);
+    match registry.latest_version(&query_name).await {
+        Ok(mut latest) => {
+            let tagged_latest = latest.value.version.clone();
+            if !options.is_allowed(&query_name, &latest.value.version)
+                || options.published_before.is_some()
+                || target != Target::Latest
+            {
+                let packument = match registry.packument(&query_name).await {
+                    Ok(packument) => packument,
+                    Err(err) => return fetch_failed(package_name, err),
+                };
+                let range = target_range(target, ver_prefix, &cmp_ver);
+                // Without a range to stay in, the target would widen to latest.
+                if target != Target::Latest && range.is_none() {
+                    warnings.push(format!(
+                        "{} {} has no version for the {} target to go from, left as is",
+                        package_name, version, target
+                    ));
+                    return PackageCheck::Skipped {
+                        package_name,
+                        reason: SkipReason::UnsupportedSpec,
+                    };
+                }
+                let allowed = match best_allowed_version(
+                    &query_name,
+                    &latest.value.version,
+                    range.as_ref(),
+                    &packument.value,
+                    &options,
+                ) {
+                    // Never propose going below the current version to satisfy a restriction.
+                    Some(allowed)
+                        if coerce_version(&cmp_ver).is_some_and(|current| allowed < current) =>
+                    {
+                        return PackageCheck::Skipped {
+                            package_name,
+                            reason: SkipReason::AlreadyLatest,
+                        }
+                    }
+                    Some(allowed) => allowed.to_string(),
+                    None => {
+                        return PackageCheck::Skipped {
+                            package_name,
+                            reason: SkipReason::AlreadyLatest,
+                        }
+                    }
+                };
+                if allowed != latest.value.version {
+                    latest = match registry.version_manifest(&query_name, &allowed).await {
+                        Ok(manifest) => manifest,
+                        Err(err) => return fetch_failed(package_name, err),
+                    };
+                }
+            }
+
+            if let Some((os, cpu)) = &options.platform {
+                if section == OPTIONAL_DEP_KEY && !latest.value.supports_platform(os, cpu) {
+                    return PackageCheck::Skipped {
+                        package_name,
+                        reason: SkipReason::OtherPlatform,
+                    };
+                }
+            }
+
+            warnings.extend(casing_warning(&query_name, latest.value.name.as_deref()));
+
+            if !options.pre && is_prerelease_bump(&cmp_ver, &latest.value.version) {
+                return PackageCheck::Skipped {
+                    package_name,
+                    reason: SkipReason::PrereleaseOnly,
+                };
+            }
+
+            if latest.value.version != cmp_ver {
+                if let Some(days) = options.behind_by_days {
+                    let packument = match registry.packument(&query_name).await {
+                        Ok(packument) => packument,
+                        Err(err) => return fetch_failed(package_name, err),
+                    };
+                    if !is_behind_by(&packument.value.time, &cmp_ver, &latest.value.version, days) {
+                        return PackageCheck::Skipped {
+                            package_name,
+                            reason: SkipReason::RecentEnough,
+                        };
+                    }
+                }
+
+                let versions = if options.since_tag || options.wanted {
+                    match registry.versions(&query_name).await {
+                        Ok(versions) => versions.value,
+                        Err(err) => return fetch_failed(package_name, err),
+                    }
+                } else {
+                    vec![]
+                };
+                let since = if options.since_tag {
+                    versions_since(&cmp_ver, &versions)
+                } else {
+                    vec![]
+                };
+                let compared = if options.compared_targets.is_empty() {
+                    vec![]
+                } else {
+                    match registry.packument(&query_name).await {
+                        Ok(packument) => options
+                            .compared_targets
+                            .iter()
+                            .map(|target| {
+                                let version = best_allowed_version(
+                                    &query_name,
+                                    &tagged_latest,
+                                    target_range(*target, ver_prefix, &cmp_ver).as_ref(),
+                                    &packument.value,
+                                    &options,
+                                );
+                                (*target, version.map(|version| version.to_string()))
+                            })
+                            .collect(),
+                        Err(err) => return fetch_failed(package_name, err),
+                    }
+                };
+                let wanted = options
+                    .wanted
+                    .then(|| max_satisfying_version(&version, &versions))
+                    .flatten()
+                    .map(|wanted| wanted.to_string());
+
+                let package_update_data = PackageUpdateData {
+                    package_name,
+                    query_name,
+                    old_version: version,
+                    new_version: format!("{}{}", ver_prefix, latest.value.version),
+                    section,
+                    registry: latest.registry,
+                    since,
+                    wanted,
+                    compared,
+                    deprecated: latest.value.deprecation().map(String::from),
+                };
+
+                return PackageCheck::Update(package_update_data);
+            }
+
+            PackageCheck::Skipped {
+                package_name,
+                reason: SkipReason::AlreadyLatest,
+            }
+        }
+        Err(err) => fetch_failed(package_name, err),
+    }
+}
+
+/// Reads a dependency section of the manifest, which is empty if the manifest doesn't have it.
+fn dependency_section(
+    package_json: &Value,
+    section: &str,
+) -> Result<IndexMap<String, String>, Error> {
+    match package_json.get(section) {
+        Some(value) => parse_dependency_map(section, value),
+        None => Ok(IndexMap::new()),
+    }
+}
+
+/// Reads a dependency section into a map of package names to version specs. Numeric-looking
+/// versions stored as JSON numbers (`"package-a": 1.2`) are coerced to strings, see
+/// `numeric_versions` for the warnings about them.
+fn parse_dependency_map(section: &str, value: &Value) -> Result<IndexMap<String, String>, Error> {
+    let entries = value
+        .as_object()
+        .ok_or_else(|| eyre!("Expected \"{}\" to be an object", section))?;
+
+    let mut deps = IndexMap::new();
+    for (package_name, version) in entries {
+        let version = match version {
+            Value::String(version) => version.clone(),
+            Value::Number(number) => number.to_string(),
+            other => {
+                return Err(eyre!(
+                    "Invalid version for {} in \"{}\": {}",
+                    package_name,
+                    section,
+                    other
+                ))
+            }
+        };
+        deps.insert(package_name.clone(), version);
+    }
+
+    Ok(deps)
+}
+
+/// Warns about the versions stored as JSON numbers in the manifest's dependency sections, which
+/// are checked as the strings they read as.
+fn numeric_versions(package_json: &Value) -> Vec<String> {
+    SECTION_ORDER
+        .iter()
+        .filter_map(|section| Some((section, package_json.get(section)?.as_object()?)))
+        .flat_map(|(section, entries)| {
+            entries.iter().filter_map(move |(package_name, version)| {
+                let Value::Number(number) = version else {
+                    return None;
+                };
+                Some(format!(
+                    "{} version of {} is the number {}, treating it as \"{}\"",
+                    section, package_name, number, number
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Asks whether an update should be written, for `--interactive`. Anything but an explicit no
+/// (including just pressing enter) writes it.
+fn confirm_update(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    update: &PackageUpdateData,
+) -> Result<bool, Error> {
+    write!(
+        output,
+        "    write {} {} => {}? [Y/n] ",
+        update.package_name, update.old_version, update.new_version
+    )?;
+    output.flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(!matches!(answer.trim().to_lowercase().as_str(), "n" | "no"))
+}
+
+/// Why a reported update isn't written to package.json, if it isn't. Peer ranges are only
+/// written with `write_peer`, since bumping them carelessly breaks consumers.
+fn held_back_reason(
+    update: &PackageUpdateData,
+    skip_majors: bool,
+    write_peer: bool,
+) -> Option<&'static str> {
+    if update.section == PEER_DEP_KEY && !write_peer {
+        Some("peer ranges are only written with --write-peer")
+    } else if !is_writable(update, skip_majors) {
+        Some("crosses a major version")
+    } else {
+        None
+    }
+}
+
+/// Returns true if the update may be written to package.json. Updates crossing a major version
+/// are still reported but held back when `skip_majors` is set.
+fn is_writable(update: &PackageUpdateData, skip_majors: bool) -> bool {
+    !(skip_majors && crosses_major(&update.old_version, &update.new_version))
+}
+
+/// Returns true if moving from `old_version` to `new_version` crosses a major version. Versions
+/// that can't be compared are assumed to cross one.
+fn crosses_major(old_version: &str, new_version: &str) -> bool {
+    matches!(
+        classify_bump(old_version, new_version),
+        Bump::Major | Bump::Other
+    )
+}
+
+/// Classifies the change between two versions by the most significant part that differs.
+fn classify_bump(old_version: &str, new_version: &str) -> Bump {
+    let parse = |version: &str| Version::parse(version.trim_start_matches(['^', '~'])).ok();
+    match (parse(old_version), parse(new_version)) {
+        (Some(old), Some(new)) if old.major != new.major => Bump::Major,
+        (Some(old), Some(new)) if old.minor != new.minor => Bump::Minor,
+        (Some(_), Some(_)) => Bump::Patch,
+        _ => Bump::Other,
+    }
+}
+
+/// Formats an update as a GitHub Actions workflow command, so it shows up as an annotation.
+/// Major (and unclassifiable) bumps are warnings, minor and patch bumps notices.
+fn github_annotation(update: &PackageUpdateData) -> String {
+    let bump = classify_bump(&update.old_version, &update.new_version);
+    let level = match bump {
+        Bump::Major | Bump::Other => "warning",
+        Bump::Minor | Bump::Patch => "notice",
+    };
+
+    format!(
+        "::{} title=Outdated dependency::{} {} => {} ({})",
+        level, update.package_name, update.old_version, update.new_version, bump
+    )
+}
+
+/// Warns when a manifest key differs only in case from the registry's canonical package name.
+fn casing_warning(package_name: &str, canonical_name: Option<&str>) -> Option<String> {
+    let canonical_name = canonical_name?;
+    if canonical_name == package_name || !canonical_name.eq_ignore_ascii_case(package_name) {
+        return None;
+    }
+
+    Some(format!(
+        "{} is published as {}, package names are case sensitive",
+        package_name, canonical_name
+    ))
+}
+
+/// Whether moving from `current` to `new` only changes the prerelease tag of the same version.
+fn is_prerelease_bump(current: &str, new: &str) -> bool {
+    match (Version::parse(current), Version::parse(new)) {
+        (Ok(current), Ok(new)) => {
+            !current.pre.is_empty()
+                && !new.pre.is_empty()
+                && (current.major, current.minor, current.patch)
+                    == (new.major, new.minor, new.patch)
+        }
+        _ => false,
+    }
+}
+
+/// Fills in the missing parts of a partial version the way node-semver's `coerce` does, so `1`
+/// becomes `1.0.0` and `1.2` becomes `1.2.0`. Full versions are returned as they are.
+fn coerce_version(version: &str) -> Option<Version> {
+    let version = version.trim().trim_start_matches(['=', 'v']);
+    if let Ok(version) = Version::parse(version) {
+        return Some(version);
+    }
+
+    let parts: Vec<u64> = version
+        .split('.')
+        .map(str::parse)
+        .collect::<Result<_, _>>()
+        .ok()?;
+    match parts[..] {
+        [major] => Some(Version::new(major, 0, 0)),
+        [major, minor] => Some(Version::new(major, minor, 0)),
+        _ => None,
+    }
+}
+
+/// Lists the published versions newer than `current`, oldest first.
+fn versions_since(current: &str, versions: &[String]) -> Vec<String> {
+    let current = match Version::parse(current) {
+        Ok(current) => current,
+        Err(_) => return vec![],
+    };
+
+    let mut since: Vec<Version> = versions
+        .iter()
+        .filter_map(|version| Version::parse(version).ok())
+        .filter(|version| *version > current)
+        .collect();
+    since.sort();
+
+    since.iter().map(Version::to_string).collect()
+}
+
+/// The `--dep` types asked for, with `--only-prod` standing for `--dep prod`.
+fn dep_types(matches: &ArgMatches) -> Option<&str> {
+    if matches.is_present("only-prod") {
+        Some("prod")
+    } else {
+        matches.value_of("dep")
+    }
+}
+
+/// The dependency sections left out by a `--dep` list of types. Without one, every section is
+/// checked.
+fn skipped_sections(types: Option<&str>) -> Result<Vec<&'static str>, Error> {
+    let types = match types {
+        Some(types) => split_patterns(Some(types)),
+        None => return Ok(vec![]),
+    };
+    if let Some(unknown) = types
+        .iter()
+        .find(|name| !DEP_TYPES.iter().any(|(type_name, _)| type_name == name))
+    {
+        return Err(eyre!(
+            "Unknown dependency type '{}', expected one of: prod, dev, peer, optional",
+            unknown
+        ));
+    }
+
+    Ok(DEP_TYPES
+        .iter()
+        .filter(|(type_name, _)| !types.iter().any(|name| name == type_name))
+        .map(|(_, section)| *section)
+        .collect())
+}
+
+/// The current platform in npm's `os`/`cpu` naming (`darwin`, `win32`, `x64`, ...).
+fn current_platform() -> (String, String) {
+    let os = match env::consts::OS {
+        "macos" => "darwin",
+        "windows" => "win32",
+        os => os,
+    };
+    let cpu = match env::consts::ARCH {
+        "x86_64" => "x64",
+        "x86" => "ia32",
+        "aarch64" => "arm64",
+        cpu => cpu,
+    };
+
+    (os.to_string(), cpu.to_string())
+}
+
+/// Splits a comma-separated `--filter` or `--reject` value into its patterns.
+fn split_patterns(patterns: Option<&str>) -> Vec<String> {
+    patterns
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Matches a package name against a pattern. Patterns with `*` wildcards must match the whole
+/// name (`@mycompany/*`), others match anywhere in it.
+fn matches_pattern(package_name: &str, pattern: &str) -> bool {
+    if !pattern.contains('*') {
+        return package_name.contains(pattern);
+    }
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let mut rest = match package_name.strip_prefix(first) {
+        Some(rest) => rest,
+        None => return false,
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+/// Extracts the package a yarn resolution applies to from its (possibly glob) path, so that
+/// `**/left-pad` and `webpack/**/@scope/thing` query `left-pad` and `@scope/thing`.
+fn resolution_package_name(resolution: &str) -> &str {
+    let segments: Vec<&str> = resolution.rsplitn(3, '/').collect();
+    match segments.as_slice() {
+        [name, scope, ..] if scope.starts_with('@') => {
+            &resolution[resolution.len() - name.len() - scope.len() - 1..]
+        }
+        [name, ..] => name,
+        [] => resolution,
+    }
+}
+
+/// Parses a `--max-age-cache-per-package` spec in the form `<package>=<minutes>`.
+fn parse_package_ttl(spec: &str) -> Result<(String, Duration), Error> {
+    let parsed = spec.rsplit_once('=').and_then(|(package_name, minutes)| {
+        let minutes: u64 = minutes.trim().parse().ok()?;
+        (!package_name.is_empty())
+            .then(|| (package_name.to_string(), Duration::from_secs(minutes * 60)))
+    });
+
+    parsed.ok_or_else(|| {
+        eyre!(
+            "Invalid --max-age-cache-per-package '{}', expected <package>=<minutes>",
+            spec
+        )
+    })
+}
+
+/// Parses an `--exclude-version` spec of the form `<package>@<version>`.
+fn parse_excluded_version(spec: &str) -> Result<(String, String), Error> {
+    match spec.rsplit_once('@') {
+        Some((package_name, version)) if !package_name.is_empty() && !version.is_empty() => {
+            Ok((package_name.to_string(), version.to_string()))
+        }
+        _ => Err(eyre!(
+            "Invalid --exclude-version '{}', expected <package>@<version>",
+            spec
+        )),
+    }
+}
+
+/// Picks the greatest stable version no newer than `latest` that is allowed, within `range` if
+/// given and, with `--before`/`--min-age`, was published before the cutoff.
+fn best_allowed_version(
+    package_name: &str,
+    latest: &str,
+    range: Option<&VersionReq>,
+    packument: &Packument,
+    options: &CheckOptions,
+) -> Option<Version> {
+    let latest = Version::parse(latest).ok()?;
+
+    packument
+        .versions
+        .keys()
+        .filter(|version| options.is_allowed(package_name, version))
+        .filter(|version| {
+            options.published_before.as_ref().is_none_or(|cutoff| {
+                packument
+                    .time
+                    .get(*version)
+                    .is_some_and(|published| published < cutoff)
+            })
+        })
+        .filter_map(|version| Version::parse(version).ok())
+        .filter(|version| version.pre.is_empty() && *version <= latest)
+        .filter(|version| range.is_none_or(|range| range.matches(version)))
+        .max()
+}
+
+/// The versions a `minor` or `patch` target may move to from `current`, further limited by the
+/// spec's own `^`/`~` range so a tilde never crosses a minor and a caret never crosses a major.
+/// A partial `current` such as `1.2` is filled in the way `--loose` does; `None` for `latest`,
+/// or when `current` isn't a version at all.
+fn target_range(target: Target, prefix: &str, current: &str) -> Option<VersionReq> {
+    let version = coerce_version(current)?;
+    let upper = match target {
+        Target::Latest => return None,
+        Target::Minor => format!("<{}.0.0", version.major + 1),
+        Target::Patch => format!("<{}.{}.0", version.major, version.minor + 1),
+    };
+
+    let mut range = format!(">={}, {}", version, upper);
+    if prefix == "^" || prefix == "~" {
+        range = format!("{}, {}{}", range, prefix, version);
+    }
+
+    VersionReq::parse(&range).ok()
+}
+
+/// The targets from `--target`, falling back to the `NCU_TARGET` environment variable and then
+/// to `latest`. Either may list several, such as `latest,minor`; the first one is upgraded to.
+fn resolve_targets(flag: Option<&str>, env_target: Option<&str>) -> Result<Vec<Target>, Error> {
+    match flag.or(env_target) {
+        Some(targets) => split_patterns(Some(targets))
+            .iter()
+            .map(|target| target.parse())
+            .collect::<Result<Vec<_>, _>>()
+            .and_then(|targets| {
+                (!targets.is_empty())
+                    .then_some(targets)
+                    .ok_or_else(|| eyre!("Expected at least one target"))
+            }),
+        None => Ok(vec![Target::Latest]),
+    }
+}
+
+/// The cutoff timestamp for `--before` and `--min-age`, whichever is earlier. Registry
+/// timestamps are ISO 8601 in UTC, so they order correctly as plain strings.
+fn publish_cutoff(
+    before: Option<&str>,
+    min_age_days: Option<u64>,
+) -> Result<Option<String>, Error> {
+    if let Some(before) = before {
+        let date = before.get(..10).unwrap_or_default();
+        let is_date = date.bytes().enumerate().all(|(i, byte)| match i {
+            4 | 7 => byte == b'-',
+            _ => byte.is_ascii_digit(),
+        });
+        if date.len() != 10 || !is_date {
+            return Err(eyre!("Invalid date {}, expected YYYY-MM-DD", before));
+        }
+    }
+
+    let min_age_cutoff = min_age_days.map(|days| {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        iso_timestamp(now.saturating_sub(days * 24 * 60 * 60))
+    });
+
+    Ok([before.map(String::from), min_age_cutoff]
+        .into_iter()
+        .flatten()
+        .min())
+}
+
+/// Whether `latest` was published more than `days` days after `current`, going by the packument's
+/// `time` map. When either date is unknown the package counts as behind, so it isn't hidden.
+fn is_behind_by(time: &IndexMap<String, String>, current: &str, latest: &str, days: u64) -> bool {
+    let day = |version: &str| time.get(version).and_then(|published| epoch_day(published));
+    match (day(current), day(latest)) {
+        (Some(current), Some(latest)) => latest - current > days as i64,
+        _ => true,
+    }
+}
+
+/// The number of days since the Unix epoch of the date an ISO 8601 timestamp falls on.
+fn epoch_day(timestamp: &str) -> Option<i64> {
+    let mut parts = timestamp.get(..10)?.split('-').map(str::parse::<i64>);
+    let (year, month, day) = (
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+        parts.next()?.ok()?,
+    );
+
+    // The inverse of the conversion in `iso_timestamp`, see
+    // http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146097 + day_of_era - 719468)
+}
+
+/// Formats seconds since the Unix epoch as an ISO 8601 UTC timestamp.
+fn iso_timestamp(secs: u64) -> String {
+    // Converts days since the epoch to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    let secs_of_day = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
+
+/// A failed registry lookup for the given package, reported once every package is checked.
+fn fetch_failed(package_name: String, err: Error) -> PackageCheck {
+    PackageCheck::Failed {
+        package_name,
+        error: err.to_string(),
+    }
+}
+
+/// The greatest published version `range` allows, which is what `npm update` installs.
+fn max_satisfying_version(range: &str, versions: &[String]) -> Option<Version> {
+    // A bare version is an exact pin in npm, whereas the semver crate treats it as a caret range.
+    let req = if range.starts_with(|c: char| c.is_ascii_digit()) {
+        VersionReq::parse(&format!("={}", range)).ok()?
+    } else {
+        VersionReq::parse(&npm_range_to_semver(range)).ok()?
+    };
+
+    versions
+        .iter()
+        .filter_map(|version| Version::parse(version).ok())
+        .filter(|version| req.matches(version))
+        .max()
+}
+
+/// Returns the published version nearest to `range` when the range no longer matches any of the
+/// published versions, or None if it is still satisfiable. The lowest version above the range's
+/// floor is preferred, falling back to the highest version below it.
+fn min_satisfying_version(range: &str, versions: &[String]) -> Option<Version> {
+    let floor = Version::parse(range.trim_start_matches(['^', '~', '='])).ok()?;
+    // A bare version is an exact pin in npm, whereas the semver crate treats it as a caret range.
+    let req = if range.starts_with(|c: char| c.is_ascii_digit()) {
+        VersionReq::parse(&format!("={}", range)).ok()?
+    } else {
+        VersionReq::parse(range).ok()?
+    };
+
+    let mut published: Vec<Version> = versions
+        .iter()
+        .filter_map(|version| Version::parse(version).ok())
+        .collect();
+    published.sort();
+
+    if published.iter().any(|version| req.matches(version)) {
+        return None;
+    }
+
+    published
+        .iter()
+        .find(|version| **version > floor)
+        .or_else(|| published.iter().rev().find(|version| **version < floor))
+        .cloned()
+}
+
+/// Splits a `>=1.0.0` or `>1.0.0` spec into its operator and floor version.
+fn comparison_floor(spec: &str) -> Option<(&'static str, &str)> {
+    let (operator, floor) = match spec.strip_prefix(">=") {
+        Some(floor) => (">=", floor),
+        None => (">", spec.strip_prefix('>')?),
+    };
+    let floor = floor.trim();
+
+    Version::parse(floor).ok().map(|_| (operator, floor))
+}
+
+/// Parses `--rewrite-protocol` rules, comma-separated `<from>=<to>` prefix mappings.
+fn parse_rewrite_rules(rules: &str) -> Result<Vec<(String, String)>, Error> {
+    rules
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| match rule.split_once('=') {
+            Some((from, to)) if !from.is_empty() => Ok((from.to_string(), to.to_string())),
+            _ => Err(eyre!(
+                "Invalid rewrite rule '{}', expected <from>=<to>",
+                rule
+            )),
+        })
+        .collect()
+}
+
+/// Applies the first rewrite rule whose prefix the spec starts with, keeping the rest of the
+/// spec (and so its version numbers) as it is. Returns `None` if no rule changes the spec.
+fn rewrite_spec(spec: &str, rules: &[(String, String)]) -> Option<String> {
+    rules.iter().find_map(|(from, to)| {
+        let rest = spec.strip_prefix(from.as_str())?;
+        (from != to).then(|| format!("{}{}", to, rest))
+    })
+}
+
+/// Describes each dependency whose position sorting its section alphabetically would change,
+/// as `section.name: from => to` with positions counted from 1.
+fn sort_moves(package_json: &Value) -> Vec<String> {
+    let mut moves = vec![];
+    for section in SECTION_ORDER {
+        let entries = match package_json.get(section).and_then(Value::as_object) {
+            Some(entries) => entries,
+            None => continue,
+        };
+
+        let mut sorted: Vec<&String> = entries.keys().collect();
+        sorted.sort();
+        for (from, package_name) in entries.keys().enumerate() {
+            let to = sorted
+                .iter()
+                .position(|sorted_name| *sorted_name == package_name)
+                .unwrap_or(from);
+            if to != from {
+                moves.push(format!(
+                    "{}.{}: {} => {}",
+                    section,
+                    package_name,
+                    from + 1,
+                    to + 1
+                ));
+            }
+        }
+    }
+
+    moves
+}
+
+/// Checks every dependency spec in the manifest offline, describing each malformed entry.
+fn validate_manifest(package_json: &Value) -> Vec<String> {
+    let mut problems = vec![];
+    for section in SECTION_ORDER {
+        let entries = match package_json.get(section) {
+            Some(Value::Object(entries)) => entries,
+            Some(other) => {
+                problems.push(format!("{}: expected an object, found {}", section, other));
+                continue;
+            }
+            None => continue,
+        };
+
+        for (package_name, spec) in entries {
+            let result = match spec {
+                Value::String(spec) => validate_spec(spec),
+                other => Err(format!("expected a string, found {}", other)),
+            };
+            if let Err(reason) = result {
+                problems.push(format!(
+                    "{}.{}: {} ({})",
+                    section, package_name, spec, reason
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Checks that a spec is a semver version or range, a dist-tag, or uses a known protocol.
+fn validate_spec(spec: &str) -> Result<(), String> {
+    let spec = spec.trim();
+    if spec.is_empty()
+        || is_git_spec(spec)
+        || SPEC_PROTOCOLS
+            .iter()
+            .any(|protocol| spec.starts_with(protocol))
+    {
+        return Ok(());
+    }
+
+    let range = spec.split("||").try_for_each(|range| {
+        let range = range.trim();
+        if range.is_empty() {
+            return Ok(());
+        }
+        VersionReq::parse(&npm_range_to_semver(range)).map(|_| ())
+    });
+
+    // Anything that isn't a range may still be a dist-tag such as `latest` or `next`.
+    let is_tag = spec.starts_with(|c: char| c.is_ascii_alphabetic())
+        && spec
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c));
+
+    match range {
+        Err(err) if !is_tag => Err(err.to_string()),
+        _ => Ok(()),
+    }
+}
+
+/// Rewrites a single npm range (no `||`) into the comma separated syntax of the semver crate:
+/// `>= 1.0.0 <2` becomes `>=1.0.0, <2`, and `1.0.0 - 2.0.0` becomes `>=1.0.0, <=2.0.0`.
+fn npm_range_to_semver(range: &str) -> String {
+    if let Some((from, to)) = range.split_once(" - ") {
+        return format!(">={}, <={}", from.trim(), to.trim());
+    }
+
+    let mut comparators: Vec<String> = vec![];
+    let mut operator = String::new();
+    for token in range.split_whitespace() {
+        if token.chars().all(|c| "<>=~^".contains(c)) {
+            operator.push_str(token);
+            continue;
+        }
+        let split = token
+            .find(|c: char| !"<>=~^".contains(c))
+            .unwrap_or(token.len());
+        let (token_operator, version) = token.split_at(split);
+        comparators.push(format!(
+            "{}{}{}",
+            operator,
+            token_operator,
+            version.strip_prefix('v').unwrap_or(version)
+        ));
+        operator.clear();
+    }
+
+    comparators.join(", ")
+}
+
+/// Classifies specs that can't be bumped to a registry version, so they're left untouched: git
+/// repositories, local paths and other protocols, dist-tags such as `latest`, and wildcards.
+fn unsupported_spec(spec: &str) -> Option<SkipReason> {
+    if is_git_spec(spec) {
+        return Some(SkipReason::GitDependency);
+    }
+    if comparison_floor(spec).is_some() {
+        return None;
+    }
+
+    let bare = spec.trim().trim_start_matches(['^', '~', '=', 'v']);
+    let is_wildcard = bare
+        .split(['.', '-', '+'])
+        .take(3)
+        .any(|part| matches!(part, "x" | "X" | "*"));
+    let is_unsupported = SPEC_PROTOCOLS
+        .iter()
+        .any(|protocol| spec.starts_with(protocol))
+        || !bare.starts_with(|c: char| c.is_ascii_digit())
+        || is_wildcard;
+
+    is_unsupported.then_some(SkipReason::UnsupportedSpec)
+}
+
+/// Returns true if the version spec points at a git repository rather than a registry version.
+fn is_git_spec(version: &str) -> bool {
+    GIT_PREFIXES
+        .iter()
+        .any(|prefix| version.starts_with(prefix))
+}
+
+/// Reads the version of a dependency actually installed under the project's `node_modules`.
+fn installed_version(project_dir: &Path, package_name: &str) -> Option<String> {
+    let manifest_path = project_dir
+        .join("node_modules")
+        .join(package_name)
+        .join("package.json");
+    let manifest: Value = serde_json::from_str(&fs::read_to_string(manifest_path).ok()?).ok()?;
+
+    manifest.get("version")?.as_str().map(String::from)
+}
+
+/// Cross-checks bun's `trustedDependencies`, the packages allowed to run lifecycle scripts,
+/// against the dependency sections, warning about names that aren't a direct dependency.
+fn unknown_trusted_dependencies(
+    package_json: &Value,
+    sections: &[&IndexMap<String, String>],
+) -> Vec<String> {
+    let trusted = match package_json.get("trustedDependencies") {
+        Some(Value::Array(names)) => names,
+        _ => return vec![],
+    };
+
+    trusted
+        .iter()
+        .filter_map(Value::as_str)
+        .filter(|package_name| !sections.iter().any(|deps| deps.contains_key(*package_name)))
+        .map(|package_name| format!("{} is trusted but isn't a direct dependency", package_name))
+        .collect()
+}
+
+/// Cross-checks `bundledDependencies` against `dependencies`: every bundled package should be
+/// declared there, and the copy installed for bundling should satisfy its declared range.
+fn bundled_drift(
+    package_json: &Value,
+    deps: &IndexMap<String, String>,
+    project_dir: &Path,
+) -> Vec<String> {
+    let bundled: Vec<&str> = match package_json
+        .get("bundledDependencies")
+        .or_else(|| package_json.get("bundleDependencies"))
+    {
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        Some(Value::Bool(true)) => deps.keys().map(String::as_str).collect(),
+        _ => return vec![],
+    };
+
+    let mut warnings = vec![];
+    for package_name in bundled {
+        let range = match deps.get(package_name) {
+            Some(range) => range,
+            None => {
+                warnings.push(format!(
+                    "{} is bundled but not declared in \"{}\"",
+                    package_name, DEP_KEY
+                ));
+                continue;
+            }
+        };
+        let installed = match installed_version(project_dir, package_name) {
+            Some(installed) => installed,
+            None => continue,
+        };
+
+        let satisfied = match (
+            VersionReq::parse(&npm_range_to_semver(range)),
+            Version::parse(&installed),
+        ) {
+            (Ok(range), Ok(installed)) => range.matches(&installed),
+            _ => range.trim_start_matches(['^', '~', '=']) == installed,
+        };
+        if !satisfied {
+            warnings.push(format!(
+                "bundled {} is {}, which doesn't match its declared {}",
+                package_name, installed, range
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// Prints a warning, to stderr with `--json` so stdout stays valid JSON.
+fn print_warning(warning: &str, json_output: bool) {
+    print_line(&format!("Warning: {}", warning), json_output);
+}
+
+/// Prints a line next to the report, to stderr with `--json` so stdout stays valid JSON.
+fn print_line(line: &str, json_output: bool) {
+    if json_output {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+}
+
+/// Renders a user-facing message template, substituting `{path}` with the given path.
+fn render_message(template: &str, path: &str) -> String {
+    template.replace("{path}", path)
+}
+
+/// The directory containing the given package.json.
+fn project_dir(path: &str) -> &Path {
+    Path::new(path)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."))
+}
+
+/// Runs the install command and then the test command, if given, after updates have been written.
+/// The first one to fail reverts every written file in `originals` and fails the run.
+fn run_post_write_commands(
+    path: &str,
+    originals: &[(&Path, &str)],
+    install_command: Option<&str>,
+    test_command: Option<&str>,
+) -> Result<(), Error> {
+    for (kind, command) in [("Install", install_command), ("Test", test_command)] {
+        if let Some(command) = command {
+            if !run_test_command(command, path, originals)? {
+                let reverted: Vec<String> = originals
+                    .iter()
+                    .map(|(file, _)| file.display().to_string())
+                    .collect();
+                return Err(eyre!(
+                    "{} command `{}` failed, {} has been reverted",
+                    kind,
+                    command,
+                    reverted.join(" and ")
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The package manager the project uses: the one named by its `packageManager` field, or else
+/// the one whose lockfile sits next to package.json, or npm.
+fn package_manager<'a>(package_json: &'a Value, project_dir: &Path) -> &'a str {
+    let declared = package_json["packageManager"]
+        .as_str()
+        .and_then(|spec| spec.split('@').next())
+        .filter(|name| !name.is_empty());
+    declared.unwrap_or_else(|| {
+        [
+            ("pnpm-lock.yaml", "pnpm"),
+            ("yarn.lock", "yarn"),
+            ("bun.lockb", "bun"),
+            ("bun.lock", "bun"),
+        ]
+        .into_iter()
+        .find(|(lockfile, _)| project_dir.join(lockfile).is_file())
+        .map_or("npm", |(_, package_manager)| package_manager)
+    })
+}
+
+/// The install command of the package manager the project uses, optionally without running
+/// the lifecycle scripts of what it installs.
+fn install_command(package_json: &Value, project_dir: &Path, ignore_scripts: bool) -> String {
+    let mut command = vec![package_manager(package_json, project_dir), "install"];
+    if ignore_scripts {
+        command.push("--ignore-scripts");
+    }
+    command.join(" ")
+}
+
+/// The command that brings the project's lockfile in line with package.json, installing as
+/// little as the package manager allows.
+fn lockfile_command(package_json: &Value, project_dir: &Path) -> String {
+    match package_manager(package_json, project_dir) {
+        "npm" => "npm install --package-lock-only".to_string(),
+        "pnpm" => "pnpm install --lockfile-only".to_string(),
+        package_manager => format!("{} install", package_manager),
+    }
+}
+
+/// Runs a command in the package's directory after updates have been written. If it exits
+/// unsuccessfully, each file in `originals` (package.json, and the catalog file if it was
+/// updated too) is restored to its contents before the write. Returns whether it passed.
+fn run_test_command(command: &str, path: &str, originals: &[(&Path, &str)]) -> Result<bool, Error> {
+    let project_dir = project_dir(path);
+
+    let status = if cfg!(windows) {
+        Command::new("cmd")
+            .args(["/C", command])
+            .current_dir(project_dir)
+            .status()?
+    } else {
+        Command::new("sh")
+            .args(["-c", command])
+            .current_dir(project_dir)
+            .status()?
+    };
+
+    if !status.success() {
+        for (file, contents) in originals {
+            fs::write(file, contents)?;
+        }
+    }
+
+    Ok(status.success())
+}
+
+/// A fresh scratch directory for `--from-tarball` and `--from-git`, removed again when dropped.
+/// The process id and a counter make its name unique, so concurrent runs don't share one.
+struct AuditDir(PathBuf);
+
+impl AuditDir {
+    fn new() -> Result<Self, Error> {
+        static CREATED: AtomicU32 = AtomicU32::new(0);
+        loop {
+            let path = env::temp_dir().join(format!(
+                "{}-audit-{}-{}",
+                env!("CARGO_PKG_NAME"),
+                std::process::id(),
+                CREATED.fetch_add(1, Ordering::Relaxed)
+            ));
+            // Left behind by a killed run that had the same process id.
+            match fs::create_dir(&path) {
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+                result => result?,
+            }
+            return Ok(AuditDir(path));
+        }
+    }
+}
+
+impl std::ops::Deref for AuditDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for AuditDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs one of the external tools the audits rely on, failing clearly when it isn't installed.
+fn run_tool(command: &mut Command, tool: &str) -> Result<std::process::Output, Error> {
+    command.output().map_err(|err| match err.kind() {
+        io::ErrorKind::NotFound => eyre!("`{}` must be installed and on the PATH", tool),
+        _ => eyre!("Could not run `{}`: {}", tool, err),
+    })
+}
+
+/// Downloads an npm package tarball (unless it is a local file) and extracts its package.json
+/// into `dir`, returning where it was written.
+async fn tarball_manifest(client: &Client, tarball: &str, dir: &Path) -> Result<PathBuf, Error> {
+    let tarball = if tarball.starts_with("http://") || tarball.starts_with("https://") {
+        let bytes = client
+            .get(tarball)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let download = dir.join("package.tgz");
+        fs::write(&download, bytes)?;
+        download
+    } else {
+        PathBuf::from(tarball)
+    };
+
+    let manifest = dir.join("package.json");
+    fs::write(&manifest, extract_manifest(&tarball)?)?;
+    Ok(manifest)
+}
+
+/// Reads the package.json at the top of a gzipped tarball. npm packs it under `package/`, but
+/// some registries use another directory name, so whichever top-level directory holds it is used.
+fn extract_manifest(tarball: &Path) -> Result<String, Error> {
+    let listing = run_tool(Command::new("tar").arg("-tzf").arg(tarball), "tar")?;
+    if !listing.status.success() {
+        return Err(eyre!(
+            "Could not read the tarball {}: {}",
+            tarball.display(),
+            String::from_utf8_lossy(&listing.stderr).trim()
+        ));
+    }
+    let listing = String::from_utf8_lossy(&listing.stdout);
+    let entry = listing
+        .lines()
+        .find(|entry| entry.ends_with("/package.json") && entry.matches('/').count() == 1)
+        .ok_or_else(|| eyre!("{} has no package.json", tarball.display()))?;
+
+    let extracted = run_tool(
+        Command::new("tar").arg("-xzOf").arg(tarball).arg(entry),
+        "tar",
+    )?;
+    if !extracted.status.success() {
+        return Err(eyre!(
+            "Could not extract {} from {}",
+            entry,
+            tarball.display()
+        ));
+    }
+    Ok(String::from_utf8(extracted.stdout)?)
+}
+
+/// Shallowly clones a git repository into the empty `dir`, returning the path of its package.json.
+fn git_manifest(url: &str, dir: &Path) -> Result<PathBuf, Error> {
+    let cloned = run_tool(
+        Command::new("git")
+            // `--` so a URL starting with a dash can't be taken for an option.
+            .args(["clone", "--depth", "1", "--quiet", "--", url])
+            .arg(dir),
+        "git",
+    )?;
+    if !cloned.status.success() {
+        return Err(eyre!(
+            "Could not clone {}: {}",
+            url,
+            String::from_utf8_lossy(&cloned.stderr).trim()
+        ));
+    }
+    Ok(dir.join("package.json"))
+}
+
+/// Rewrites only the version strings that changed from `package_json`, keeping the rest of
+/// `contents` byte for byte.
+fn edit_manifest_versions(
+    contents: &str,
+    package_json: &Value,
+    sections: &[(&str, &IndexMap<String, String>)],
+) -> Result<String, Error> {
+    let mut contents = contents.to_string();
+    for (section, versions) in sections {
+        for (package_name, version) in versions.iter() {
+            if package_json[section][package_name].as_str() == Some(version) {
+                continue;
+            }
+            contents = jsonc::set_string(&contents, &[section, package_name], version)
+                .ok_or_else(|| eyre!("Could not find {} in {}", package_name, section))?;
+        }
+    }
+
+    Ok(contents)
+}
+
+/// Saves the original contents of a manifest next to it as `<path>.bak`, returning the backup's
+/// path.
+fn write_backup(path: &str, original_contents: &str) -> Result<String, Error> {
+    let backup_path = format!("{}.bak", path);
+    fs::write(&backup_path, original_contents)?;
+
+    Ok(backup_path)
+}
+
+/// Splits a leading byte order mark off a manifest, which JSON parsers reject, returning the
+/// rest and whether there was one.
+fn strip_bom(contents: &str) -> (&str, bool) {
+    match contents.strip_prefix(BOM) {
+        Some(rest) => (rest, true),
+        None => (contents, false),
+    }
+}
+
+/// Writes a manifest, starting it with a byte order mark if `bom` is set.
+fn write_manifest(path: &str, contents: &str, bom: bool) -> Result<(), Error> {
+    if bom {
+        fs::write(path, format!("{}{}", BOM, contents))?;
+    } else {
+        fs::write(path, contents)?;
+    }
+    Ok(())
+}
+
+/// Re-reads a written manifest to confirm that it still parses as JSON.
+fn verify_written_manifest(path: &str) -> Result<(), Error> {
+    let contents = fs::read_to_string(path)?;
+    serde_json::from_str::<Value>(&jsonc::strip_comments(strip_bom(&contents).0))
+        .map_err(|err| eyre!("{} is no longer valid JSON after writing: {}", path, err))?;
+
+    Ok(())
+}
+
+/// Reads the catalog entries declared in a `pnpm-workspace.yaml`.
+fn parse_catalogs(contents: &str) -> Result<Catalogs, Error> {
+    if contents.trim().is_empty() {
+        return Ok(vec![]);
+    }
+
+    let workspace: PnpmWorkspace = serde_yaml::from_str(contents)?;
+
+    let mut catalogs = vec![];
+    if !workspace.catalog.is_empty() {
+        catalogs.push((None, workspace.catalog));
+    }
+    for (name, entries) in workspace.catalogs {
+        catalogs.push((Some(name), entries));
+    }
+
+    Ok(catalogs)
+}
+
+/// The `catalog:` protocol reference used in package.json for the given catalog.
+fn catalog_label(catalog: &Option<String>) -> String {
+    match catalog {
+        Some(name) => format!("catalog:{}", name),
+        None => "catalog".to_string(),
+    }
+}
+
+/// Rewrites the version of a single catalog entry in a `pnpm-workspace.yaml`, editing the line
+/// in place so that comments, quoting and layout are preserved.
+fn update_catalog_yaml(
+    contents: &str,
+    catalog: Option<&str>,
+    package_name: &str,
+    new_version: &str,
+) -> String {
+    let mut section = "";
+    let mut current_catalog: Option<&str> = None;
+    let mut catalog_indent = None;
+    let mut lines = vec![];
+
+    for line in contents.split('\n') {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            lines.push(line.to_string());
+            continue;
+        }
+
+        let (key, rest) = match split_yaml_key(trimmed) {
+            Some(parts) => parts,
+            None => {
+                lines.push(line.to_string());
+                continue;
+            }
+        };
+
+        if indent == 0 {
+            section = key;
+            current_catalog = None;
+            catalog_indent = None;
+            lines.push(line.to_string());
+            continue;
+        }
+
+        // Entries of `catalogs` are nested one level deeper, under the catalog name.
+        if section == "catalogs" && catalog_indent.is_none_or(|i| indent <= i) {
+            catalog_indent = Some(indent);
+            current_catalog = Some(key);
+            lines.push(line.to_string());
+            continue;
+        }
+
+        let in_catalog = match catalog {
+            None => {
+                section == "catalog"
+                    || (section == "catalogs" && current_catalog == Some("default"))
+            }
+            Some(name) => section == "catalogs" && current_catalog == Some(name),
+        };
+
+        if in_catalog && key == package_name {
+            let value = rest.trim();
+            let (value, comment) = match value.find(" #") {
+                Some(i) => (&value[..i], &value[i..]),
+                None => (value, ""),
+            };
+            let quote = value.chars().next().filter(|c| *c == '"' || *c == '\'');
+            let new_value = match quote {
+                Some(q) => format!("{}{}{}", q, new_version, q),
+                None => new_version.to_string(),
+            };
+            let key_len = trimmed.len() - rest.len();
+            lines.push(format!(
+                "{}{} {}{}",
+                &line[..indent],
+                &trimmed[..key_len],
+                new_value,
+                comment
+            ));
+            continue;
+        }
+
+        lines.push(line.to_string());
+    }
+
+    lines.join("\n")
+}
+
+/// Splits a YAML mapping line into its (unquoted) key and everything after the `:`.
+fn split_yaml_key(line: &str) -> Option<(&str, &str)> {
+    if let Some(quote) = line.chars().next().filter(|c| *c == '"' || *c == '\'') {
+        let end = line[1..].find(quote)? + 1;
+        let rest = line[end + 1..].strip_prefix(':')?;
+        return Some((&line[1..end], rest));
+    }
+
+    let colon = line.find(':')?;
+    Some((&line[..colon], &line[colon + 1..]))
+}
+
+/// Inserts new dependencies into the given package_json serde::Value. Sections the manifest
+/// didn't have are only added when they have entries, see `write_section`.
+pub fn insert_new_maps(
+    package_json: &mut Value,
+    deps: IndexMap<String, String>,
+    dev_deps: IndexMap<String, String>,
+    peer_deps: IndexMap<String, String>,
+    optional_deps: IndexMap<String, String>,
+) -> Result<(), Error> {
+    write_section(package_json, DEP_KEY, deps)?;
+    write_section(package_json, DEV_DEP_KEY, dev_deps)?;
+    write_section(package_json, PEER_DEP_KEY, peer_deps)?;
+    write_section(package_json, OPTIONAL_DEP_KEY, optional_deps)?;
+
+    Ok(())
+}
+
+/// Replaces a dependency section of the manifest. A section the manifest doesn't have yet is
+/// only added if it has entries, and goes right after the closest section preceding it in
+/// `SECTION_ORDER` (or before the closest following one), keeping the layout predictable.
+fn write_section(
+    package_json: &mut Value,
+    section: &str,
+    entries: IndexMap<String, String>,
+) -> Result<(), Error> {
+    let manifest = match package_json.as_object_mut() {
+        Some(manifest) => manifest,
+        None => return Err(eyre!("Expected package.json to be an object")),
+    };
+    if let Some(value) = manifest.get_mut(section) {
+        *value = serde_json::to_value(entries)?;
+        return Ok(());
+    }
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let order = SECTION_ORDER.iter().position(|known| *known == section);
+    let position = order.and_then(|order| {
+        let after = SECTION_ORDER[..order]
+            .iter()
+            .rev()
+            .find_map(|known| manifest.keys().position(|key| key == known))
+            .map(|index| index + 1);
+        after.or_else(|| {
+            SECTION_ORDER[order + 1..]
+                .iter()
+                .find_map(|known| manifest.keys().position(|key| key == known))
+        })
+    });
+
+    let mut entries = Some(serde_json::to_value(entries)?);
+    let mut rebuilt = serde_json::Map::new();
+    for (index, (key, value)) in std::mem::take(manifest).into_iter().enumerate() {
+        if Some(index) == position {
+            rebuilt.insert(section.to_string(), entries.take().unwrap_or_default());
+        }
+        rebuilt.insert(key, value);
+    }
+    if let Some(entries) = entries {
+        rebuilt.insert(section.to_string(), entries);
+    }
+    *manifest = rebuilt;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::{tests::mock_registry, API_URL};
+    use crate::test_dir::TestDir;
+    use serde_json::json;
+
+    fn npm_registry() -> Arc<Registry> {
+        Arc::new(Registry::new(Client::new(), API_URL.to_string(), None))
+    }
+
+    fn update_data(package_name: &str, old_version: &str, new_version: &str) -> PackageUpdateData {
+        PackageUpdateData {
+            package_name: package_name.to_string(),
+            query_name: package_name.to_string(),
+            old_version: old_version.to_string(),
+            new_version: new_version.to_string(),
+            section: DEP_KEY,
+            registry: API_URL.to_string(),
+            since: vec![],
+            wanted: None,
+            compared: vec![],
+            deprecated: None,
+        }
+    }
+
+    fn quiet_progress_bar(total: u64) -> Progress {
+        let mut pb = ProgressBar::new(total);
+        pb.show_bar = false;
+        pb.show_counter = false;
+        pb.show_message = false;
+        pb.show_percent = false;
+        pb.show_time_left = false;
+        pb.show_speed = false;
+        Progress::Bar(Box::new(pb))
+    }
+
+    fn registry_at(url: String) -> Arc<Registry> {
+        Arc::new(Registry::new(Client::new(), url, None))
+    }
+
+    /// Checks every dependency in `deps` as if declared under `dependencies`, keeping their order.
+    async fn check_all(
+        registry: &Arc<Registry>,
+        options: &Arc<CheckOptions>,
+        deps: &IndexMap<String, String>,
+    ) -> Vec<PackageCheck> {
+        check_section(registry, options, deps, DEP_KEY).await
+    }
+
+    /// Checks every dependency in `deps` as if declared under `section`, keeping their order.
+    async fn check_section(
+        registry: &Arc<Registry>,
+        options: &Arc<CheckOptions>,
+        deps: &IndexMap<String, String>,
+        section: &'static str,
+    ) -> Vec<PackageCheck> {
+        let futures = process_dependencies(registry, options, deps, section).await;
+        let mut pb = quiet_progress_bar(futures.len() as u64);
+        let mut checks = vec![];
+        await_futures(futures, &mut pb, &mut checks).await.unwrap();
+        checks
+    }
+
+    #[test]
+    fn test_insert_new_maps() {
+        let mut package_json = json!({
+            "name": "abc123",
+            "dependencies": {
+                "package-a": "^1.0.0",
+                "package-b": "^2.0.0",
+            },
+            "devDependencies": {
+                "package-c": "^3.0.0",
+                "package-d": "^4.0.0",
+            }
+        });
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^2.0.0".to_string());
+        deps.insert("package-b".to_string(), "^3.0.0".to_string());
+
+        let mut dev_deps: IndexMap<String, String> = IndexMap::new();
+        dev_deps.insert("package-c".to_string(), "^3.5.0".to_string());
+        dev_deps.insert("package-d".to_string(), "^4.0.0".to_string());
+
+        // Expect the new maps to be inserted into the package.json file.
+        let result = insert_new_maps(
+            &mut package_json,
+            deps,
+            dev_deps,
+            IndexMap::new(),
+            IndexMap::new(),
+        );
+        assert!(result.is_ok());
+        assert_eq!(
+            package_json,
+            json!({
+                "name": "abc123",
+                "dependencies": {
+                    "package-a": "^2.0.0",
+                    "package-b": "^3.0.0",
+                },
+                "devDependencies": {
+                    "package-c": "^3.5.0",
+                    "package-d": "^4.0.0",
+                }
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_process_dependencies_and_await_futures() {
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("react".to_string(), "^2.0.0".to_string());
+        deps.insert("recoil".to_string(), "~3.0.0".to_string());
+
+        let futures =
+            process_dependencies(&npm_registry(), &Default::default(), &deps, DEP_KEY).await;
+        assert_eq!(futures.len(), 2);
+
+        let mut pb = quiet_progress_bar(2);
+
+        let mut checks_vec: Vec<PackageCheck> = vec![];
+
+        let futures = await_futures(futures, &mut pb, &mut checks_vec).await;
+        assert!(futures.is_ok());
+
+        for check in checks_vec {
+            let update = match check {
+                PackageCheck::Update(update) => update,
+                _ => continue,
+            };
+
+            if update.package_name == "react" {
+                assert_eq!(update.old_version, "^2.0.0");
+                assert_ne!(update.old_version, update.new_version);
+            } else if update.package_name == "recoil" {
+                assert_eq!(update.old_version, "~3.0.0");
+                assert_ne!(update.old_version, update.new_version);
+            } else {
+                panic!("Unexpected package name: {}", update.package_name);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explain_skipped_git_dependency() {
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert(
+            "bar".to_string(),
+            "git+https://github.com/foo/bar".to_string(),
+        );
+
+        let checks_vec = check_all(&npm_registry(), &Default::default(), &deps).await;
+
+        assert_eq!(checks_vec.len(), 1);
+        assert_eq!(checks_vec[0].package_name(), "bar");
+        assert_eq!(
+            checks_vec[0].explain(&CheckOptions::default()),
+            "skipped: git dependency"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_explain_rejected_by_filter() {
+        let options = Arc::new(CheckOptions {
+            reject: split_patterns(Some("left-pad")),
+            ..Default::default()
+        });
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("left-pad".to_string(), "^1.0.0".to_string());
+
+        let registry = npm_registry();
+        let checks_vec = check_all(&registry, &options, &deps).await;
+
+        assert_eq!(checks_vec.len(), 1);
+        assert_eq!(checks_vec[0].package_name(), "left-pad");
+        assert_eq!(
+            checks_vec[0].explain(&options),
+            "skipped: rejected by filter"
+        );
+        // A rejected package is never looked up.
+        assert_eq!(registry.stats.requests.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_request_stats_count_registry_requests() {
+        let body = r#"{"version":"1.0.0"}"#;
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, body),
+            ("/package-b/latest", 200, body),
+        ])
+        .await;
+        let registry = registry_at(url);
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+        deps.insert("package-b".to_string(), "^1.0.0".to_string());
+        deps.insert(
+            "package-c".to_string(),
+            "git+https://github.com/foo/package-c".to_string(),
+        );
+
+        check_all(&registry, &Default::default(), &deps).await;
+
+        // The git dependency is never fetched.
+        assert_eq!(registry.stats.requests.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            registry.stats.bytes.load(Ordering::Relaxed),
+            2 * body.len() as u64
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wanted_and_latest_versions() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.1.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{"versions":{"1.2.0":{},"1.4.0":{},"1.5.0-beta.1":{},"2.0.0":{},"2.1.0":{}}}"#,
+            ),
+        ])
+        .await;
+        let registry = registry_at(url);
+        let options = Arc::new(CheckOptions {
+            wanted: true,
+            ..Default::default()
+        });
+
+        let check = |range: &str| {
+            let (registry, options, range) = (registry.clone(), options.clone(), range.to_string());
+            async move {
+                let package_name = "package-a".to_string();
+                let query_name = package_name.clone();
+                check_version(
+                    registry,
+                    options,
+                    package_name,
+                    query_name,
+                    range,
+                    DEP_KEY,
+                    &mut vec![],
+                )
+                .await
+            }
+        };
+        match check("^1.2.0").await {
+            PackageCheck::Update(update) => {
+                assert_eq!(update.new_version, "^2.1.0");
+                assert_eq!(update.wanted.as_deref(), Some("1.4.0"));
+            }
+            other => panic!("expected an update, got {:?}", other),
+        }
+        match check("~2.0.0").await {
+            PackageCheck::Update(update) => {
+                assert_eq!(update.new_version, "~2.1.0");
+                assert_eq!(update.wanted.as_deref(), Some("2.0.0"));
+            }
+            other => panic!("expected an update, got {:?}", other),
+        }
+
+        let versions: Vec<String> = ["1.2.0", "1.4.0"].map(String::from).to_vec();
+        assert_eq!(
+            max_satisfying_version("1.2.0", &versions),
+            Some(Version::new(1, 2, 0))
+        );
+        assert_eq!(max_satisfying_version("^3.0.0", &versions), None);
+    }
+
+    #[test]
+    fn test_min_satisfying_version() {
+        let versions: Vec<String> = ["1.0.0", "1.1.0", "2.0.0", "2.1.0"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+
+        // Nothing in the 1.2.x line or above it within major 1 was ever published.
+        assert_eq!(
+            min_satisfying_version("^1.2.0", &versions),
+            Some(Version::new(2, 0, 0))
+        );
+        assert_eq!(
+            min_satisfying_version("3.0.0", &versions),
+            Some(Version::new(2, 1, 0))
+        );
+
+        // Satisfiable ranges are left alone.
+        assert_eq!(min_satisfying_version("^1.0.0", &versions), None);
+        assert_eq!(min_satisfying_version("~2.1.0", &versions), None);
+    }
+
+    #[tokio::test]
+    async fn test_check_pnpm_catalogs() {
+        let contents = r#"packages:
+  - "packages/*"
+
+catalog:
+  react: ^18.0.0
+  "@types/node": "~20.1.0" # pinned for CI
+
+catalogs:
+  legacy:
+    react: ^17.0.0
+"#;
+        let catalogs = parse_catalogs(contents).unwrap();
+        assert_eq!(catalogs.len(), 2);
+        assert_eq!(catalogs[0].0, None);
+        assert_eq!(catalogs[0].1.get("@types/node").unwrap(), "~20.1.0");
+        assert_eq!(catalogs[1].0.as_deref(), Some("legacy"));
+        assert_eq!(catalogs[1].1.get("react").unwrap(), "^17.0.0");
+
+        let url = mock_registry(&[
+            ("/react/latest", 200, r#"{"version":"18.2.0"}"#),
+            ("/@types%2fnode/latest", 200, r#"{"version":"20.5.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
+
+        let checks_vec =
+            check_section(&registry, &Default::default(), &catalogs[0].1, CATALOG_KEY).await;
+
+        let mut new_contents = contents.to_string();
+        for check in checks_vec {
+            let update = match check {
+                PackageCheck::Update(update) => update,
+                other => panic!("Expected an update, got {:?}", other),
+            };
+            new_contents = update_catalog_yaml(
+                &new_contents,
+                None,
+                &update.package_name,
+                &update.new_version,
+            );
+        }
+
+        assert_eq!(
+            new_contents,
+            r#"packages:
+  - "packages/*"
+
+catalog:
+  react: ^18.2.0
+  "@types/node": "~20.5.0" # pinned for CI
+
+catalogs:
+  legacy:
+    react: ^17.0.0
+"#
+        );
+    }
+
+    #[test]
+    fn test_extract_manifest_from_tarball() {
+        let dir = TestDir::new("extract-manifest");
+        fs::create_dir_all(dir.join("package/lib")).unwrap();
+        let package_json = r#"{ "name": "package-a", "dependencies": { "package-b": "^1.0.0" } }"#;
+        fs::write(dir.join("package/package.json"), package_json).unwrap();
+        fs::write(dir.join("package/lib/package.json"), "{}").unwrap();
+        let tarball = dir.join("package-a-1.0.0.tgz");
+        let status = Command::new("tar")
+            .arg("-czf")
+            .arg(&tarball)
+            .arg("-C")
+            .arg(&*dir)
+            .arg("package")
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        assert_eq!(extract_manifest(&tarball).unwrap(), package_json);
+        assert!(extract_manifest(&dir.join("missing.tgz")).is_err());
+    }
+
+    #[test]
+    fn test_audit_dir_is_unique_and_removed() {
+        let (first, second) = (AuditDir::new().unwrap(), AuditDir::new().unwrap());
+        assert_ne!(first.to_path_buf(), second.to_path_buf());
+        fs::write(first.join("package.json"), "{}").unwrap();
+
+        let path = first.to_path_buf();
+        drop(first);
+        assert!(!path.exists());
+        assert!(second.exists());
+
+        let err = run_tool(
+            &mut Command::new("ncu-rs-missing-tool"),
+            "ncu-rs-missing-tool",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`ncu-rs-missing-tool` must be installed and on the PATH"
+        );
+    }
+
+    #[test]
+    fn test_bom_round_trip() {
+        let dir = TestDir::new("bom");
+        let path = dir.join("package.json");
+        let path = path.to_str().unwrap();
+        let contents = r#"{ "dependencies": { "package-a": "^2.0.0" } }"#;
+
+        write_manifest(path, contents, true).unwrap();
+        let written = fs::read(path).unwrap();
+        assert_eq!(written[..3], [0xEF, 0xBB, 0xBF]);
+        assert_eq!(written[3..], *contents.as_bytes());
+        assert!(verify_written_manifest(path).is_ok());
+        assert_eq!(
+            strip_bom(&fs::read_to_string(path).unwrap()),
+            (contents, true)
+        );
+
+        write_manifest(path, contents, false).unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), contents);
+        assert_eq!(strip_bom(contents), (contents, false));
+    }
+
+    #[tokio::test]
+    async fn test_fail_on_update_when_every_update_held_back() {
+        let url = mock_registry(&[("/package-a/latest", 200, r#"{"version":"2.0.0"}"#)]).await;
+        let dir = TestDir::new("held-back");
+        let path = dir.join("package.json");
+        let path = path.to_str().unwrap();
+        let contents = r#"{ "dependencies": { "package-a": "^1.0.0" } }"#;
+        fs::write(path, contents).unwrap();
+
+        let matches = cli()
+            .try_get_matches_from([
+                "ncu-rs",
+                "--registry",
+                &url,
+                "--no-cache",
+                "--no-update-if-same-major",
+                "--fail-on-update",
+                path,
+            ])
+            .unwrap();
+        let session = Session::new(&matches).unwrap();
+        let exit_code = check_manifest(
+            path,
+            &matches,
+            &session,
+            &CheckOptions::default(),
+            true,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(exit_code, UPDATES_AVAILABLE_EXIT_CODE);
+        let written: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(written[DEP_KEY]["package-a"], "^1.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_assume_yes_writes_and_installs() {
+        let url = mock_registry(&[("/package-a/latest", 200, r#"{"version":"2.0.0"}"#)]).await;
+        let dir = TestDir::new("assume-yes");
+        let path = dir.join("package.json");
+        let path = path.to_str().unwrap();
+        fs::write(path, r#"{ "dependencies": { "package-a": "^1.0.0" } }"#).unwrap();
+
+        let matches = cli()
+            .try_get_matches_from([
+                "ncu-rs",
+                "--registry",
+                &url,
+                "--no-cache",
+                "--install-command",
+                "touch installed",
+                "-y",
+                path,
+            ])
+            .unwrap();
+        let should_update = update_requested(None, &matches).unwrap();
+        assert!(should_update);
+        let session = Session::new(&matches).unwrap();
+        check_manifest(
+            path,
+            &matches,
+            &session,
+            &CheckOptions::default(),
+            should_update,
+            false,
+        )
+        .await
+        .unwrap();
+        let written: Value = serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap();
+        assert_eq!(written[DEP_KEY]["package-a"], "^2.0.0");
+        assert!(dir.join("installed").is_file());
+
+        let matches = cli()
+            .try_get_matches_from(["ncu-rs", "check", "-y"])
+            .unwrap();
+        let check = matches.subcommand_matches("check").unwrap();
+        assert!(update_requested(Some("check"), check).is_err());
+    }
+
+    #[test]
+    fn test_verify_written_manifest() {
+        let dir = TestDir::new("verify-written-manifest");
+        let path = dir.join("package.json");
+        let path = path.to_str().unwrap();
+
+        let package_json = json!({ "dependencies": { "package-a": "^2.0.0" } });
+        fs::write(path, serde_json::to_string_pretty(&package_json).unwrap()).unwrap();
+        assert!(verify_written_manifest(path).is_ok());
+
+        fs::write(path, r#"{ "dependencies": { "#).unwrap();
+        assert!(verify_written_manifest(path).is_err());
+    }
+
+    #[test]
+    fn test_coerce_version() {
+        assert_eq!(coerce_version("1"), Some(Version::new(1, 0, 0)));
+        assert_eq!(coerce_version("1.2"), Some(Version::new(1, 2, 0)));
+        assert_eq!(coerce_version("v1.2.3"), Some(Version::new(1, 2, 3)));
+        assert_eq!(coerce_version("1.x"), None);
+    }
+
+    #[tokio::test]
+    async fn test_loose_versions_are_coerced() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"1.0.0"}"#),
+            ("/package-b/latest", 200, r#"{"version":"1.4.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
+        let options = Arc::new(CheckOptions {
+            loose: true,
+            ..Default::default()
+        });
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "1".to_string());
+        deps.insert("package-b".to_string(), "^1.2".to_string());
+
+        let checks_vec = check_all(&registry, &options, &deps).await;
+
+        // 1 is 1.0.0, which is already the latest.
+        assert!(matches!(
+            checks_vec[0],
+            PackageCheck::Skipped {
+                reason: SkipReason::AlreadyLatest,
+                ..
+            }
+        ));
+        match &checks_vec[1] {
+            PackageCheck::Update(update) => assert_eq!(update.new_version, "^1.4.0"),
+            other => panic!("Expected an update, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_to_caps_upgrades() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"3.0.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{"versions":{"1.0.0":{},"1.5.0":{},"2.0.0":{},"3.0.0":{}}}"#,
+            ),
+            ("/package-a/1.5.0", 200, r#"{"version":"1.5.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
+        let options = Arc::new(CheckOptions {
+            to: Some(VersionReq::parse("<2").unwrap()),
+            ..Default::default()
+        });
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+
+        let checks_vec = check_all(&registry, &options, &deps).await;
+
+        match &checks_vec[0] {
+            PackageCheck::Update(update) => assert_eq!(update.new_version, "^1.5.0"),
+            other => panic!("Expected an update, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_package_in_several_sections_is_fetched_once() {
+        let url = mock_registry(&[("/package-a/latest", 200, r#"{"version":"1.2.0"}"#)]).await;
+        let registry = registry_at(url);
+        let options = Arc::new(CheckOptions::default());
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+        let mut dev_deps: IndexMap<String, String> = IndexMap::new();
+        dev_deps.insert("package-a".to_string(), "~1.1.0".to_string());
+
+        let mut checks_vec = check_all(&registry, &options, &deps).await;
+        checks_vec.extend(check_section(&registry, &options, &dev_deps, DEV_DEP_KEY).await);
+
+        assert_eq!(registry.stats.requests.load(Ordering::Relaxed), 1);
+        let new_versions: Vec<&str> = checks_vec
+            .iter()
+            .map(|check| match check {
+                PackageCheck::Update(update) => update.new_version.as_str(),
+                other => panic!("Expected an update, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(new_versions, vec!["^1.2.0", "~1.2.0"]);
+    }
+
+    #[test]
+    fn test_matches_pattern() {
+        assert!(matches_pattern("@mycompany/ui", "@mycompany/*"));
+        assert!(!matches_pattern("@other/ui", "@mycompany/*"));
+        assert!(matches_pattern("eslint-plugin-react", "eslint-*-react"));
+        assert!(!matches_pattern("eslint", "eslint*eslint"));
+        assert!(matches_pattern("@types/react-dom", "react"));
+        assert!(!matches_pattern("vue", "react"));
+    }
+
+    #[tokio::test]
+    async fn test_filter_and_reject_limit_checked_packages() {
+        // Only the packages expected to be checked are served, anything else would fail.
+        let url = mock_registry(&[
+            ("/@mycompany%2fui/latest", 200, r#"{"version":"2.0.0"}"#),
+            ("/@mycompany%2fapi/latest", 200, r#"{"version":"1.1.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
+        let options = Arc::new(CheckOptions {
+            filter: split_patterns(Some("@mycompany/*, left")),
+            reject: split_patterns(Some("*-legacy,left-pad")),
+            ..Default::default()
+        });
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("@mycompany/ui".to_string(), "^1.0.0".to_string());
+        deps.insert("@mycompany/ui-legacy".to_string(), "^1.0.0".to_string());
+        deps.insert("@mycompany/api".to_string(), "^1.0.0".to_string());
+        deps.insert("react".to_string(), "^17.0.0".to_string());
+        deps.insert("left-pad".to_string(), "^1.0.0".to_string());
+
+        let checks_vec = check_all(&registry, &options, &deps).await;
+
+        let updated: Vec<&str> = checks_vec
+            .iter()
+            .filter_map(|check| match check {
+                PackageCheck::Update(update) => Some(update.package_name.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(updated, ["@mycompany/ui", "@mycompany/api"]);
+        let rejected: Vec<&str> = checks_vec
+            .iter()
+            .filter(|check| check.explain(&options) == "skipped: rejected by filter")
+            .map(PackageCheck::package_name)
+            .collect();
+        assert_eq!(rejected, ["@mycompany/ui-legacy", "react", "left-pad"]);
+    }
+
+    #[test]
+    fn test_resolution_package_name() {
+        assert_eq!(resolution_package_name("left-pad"), "left-pad");
+        assert_eq!(resolution_package_name("**/left-pad"), "left-pad");
+        assert_eq!(resolution_package_name("webpack/**/ws"), "ws");
+        assert_eq!(resolution_package_name("@types/node"), "@types/node");
+        assert_eq!(
+            resolution_package_name("webpack/**/@scope/thing"),
+            "@scope/thing"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_resolution_glob_queries_trailing_package() {
+        let url = mock_registry(&[("/left-pad/latest", 200, r#"{"version":"1.3.0"}"#)]).await;
+        let registry = registry_at(url);
+
+        let mut resolutions: IndexMap<String, String> = IndexMap::new();
+        resolutions.insert("**/left-pad".to_string(), "1.1.0".to_string());
+        resolutions.insert(
+            "**/is-odd".to_string(),
+            "git+https://github.com/foo/is-odd".to_string(),
+        );
+
+        let mut checks_vec = check_section(
+            &registry,
+            &Default::default(),
+            &resolutions,
+            RESOLUTIONS_KEY,
+        )
+        .await;
+
+        let update = match checks_vec.remove(0) {
+            PackageCheck::Update(update) => update,
+            other => panic!("Expected an update, got {:?}", other),
+        };
+        assert_eq!(update.package_name, "**/left-pad");
+        assert_eq!(update.new_version, "1.3.0");
+        assert_eq!(update.section, RESOLUTIONS_KEY);
+
+        // The glob key is kept as-is and the version replaced in place.
+        resolutions.insert(update.package_name, update.new_version);
+        assert_eq!(
+            resolutions.keys().collect::<Vec<_>>(),
+            vec!["**/left-pad", "**/is-odd"]
+        );
+        assert_eq!(resolutions.get("**/left-pad").unwrap(), "1.3.0");
+    }
+
+    #[tokio::test]
+    async fn test_npm_alias_queries_aliased_package() {
+        let url = mock_registry(&[
+            ("/bar/latest", 200, r#"{"version":"2.1.0"}"#),
+            ("/@acme%2fui/latest", 200, r#"{"version":"2.0.3"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("foo".to_string(), "npm:bar@^1.0.0".to_string());
+        deps.insert("ui".to_string(), "npm:@acme/ui@~2.0.0".to_string());
+        deps.insert("unpinned".to_string(), "npm:bar".to_string());
+
+        let checks_vec = check_all(&registry, &Default::default(), &deps).await;
+
+        let updates: Vec<(&str, &str, &str)> = checks_vec
+            .iter()
+            .filter_map(|check| match check {
+                PackageCheck::Update(update) => Some((
+                    update.package_name.as_str(),
+                    update.old_version.as_str(),
+                    update.new_version.as_str(),
+                )),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            updates,
+            [
+                ("foo", "npm:bar@^1.0.0", "npm:bar@^2.1.0"),
+                ("ui", "npm:@acme/ui@~2.0.0", "npm:@acme/ui@~2.0.3"),
+            ]
+        );
+        assert!(matches!(
+            checks_vec[2],
+            PackageCheck::Skipped {
+                reason: SkipReason::UnsupportedSpec,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn test_installed_version() {
+        let dir = TestDir::new("installed-version");
+        let package_dir = dir.join("node_modules").join("@scope").join("package-a");
+        fs::create_dir_all(&package_dir).unwrap();
+        fs::write(
+            package_dir.join("package.json"),
+            r#"{ "name": "@scope/package-a", "version": "1.4.2" }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            installed_version(&dir, "@scope/package-a"),
+            Some("1.4.2".to_string())
+        );
+        assert_eq!(installed_version(&dir, "package-b"), None);
+    }
+
+    #[test]
+    fn test_render_message() {
+        assert_eq!(
+            render_message(DEFAULT_UPDATED_MESSAGE, "package.json"),
+            "Updated package.json. Please install the updated packages. (npm/yarn/pnpm install)!"
+        );
+        assert_eq!(
+            render_message("Bumped {path}, run `pnpm i` ({path})", "app/package.json"),
+            "Bumped app/package.json, run `pnpm i` (app/package.json)"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_numeric_dependency_versions_are_coerced() {
+        let package_json = json!({
+            "dependencies": {
+                "package-a": 1.2,
+                "package-b": "^2.0.0",
+            }
+        });
+
+        let deps = parse_dependency_map(DEP_KEY, package_json.get(DEP_KEY).unwrap()).unwrap();
+        assert_eq!(deps.get("package-a").unwrap(), "1.2");
+        assert_eq!(deps.get("package-b").unwrap(), "^2.0.0");
+
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"1.3.0"}"#),
+            ("/package-b/latest", 200, r#"{"version":"2.0.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
+
+        let checks_vec = check_all(&registry, &Default::default(), &deps).await;
+
+        match &checks_vec[0] {
+            PackageCheck::Update(update) => {
+                assert_eq!(update.old_version, "1.2");
+                assert_eq!(update.new_version, "1.3.0");
+            }
+            other => panic!("Expected an update, got {:?}", other),
+        }
+        assert_eq!(
+            checks_vec[1].explain(&CheckOptions::default()),
+            "skipped: already at latest"
+        );
+
+        let invalid = json!({ "package-a": true });
+        assert!(parse_dependency_map(DEP_KEY, &invalid).is_err());
+    }
+
+    #[test]
+    fn test_versions_since() {
+        let versions: Vec<String> = ["0.9.0", "1.0.0", "2.0.0", "1.2.0", "1.1.0"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+
+        assert_eq!(
+            versions_since("1.0.0", &versions),
+            vec!["1.1.0", "1.2.0", "2.0.0"]
+        );
+        assert!(versions_since("2.0.0", &versions).is_empty());
+        assert!(versions_since("latest", &versions).is_empty());
+    }
+
+    #[test]
+    fn test_crosses_major() {
+        assert!(crosses_major("^1.0.0", "^2.0.0"));
+        assert!(!crosses_major("^1.0.0", "^1.5.0"));
+        assert!(!crosses_major("~1.2.0", "~1.2.3"));
+        assert!(crosses_major("latest", "1.0.0"));
+
+        assert_eq!(classify_bump("1.0.0", "2.0.0"), Bump::Major);
+        assert_eq!(classify_bump("^1.0.0", "^1.1.0"), Bump::Minor);
+        assert_eq!(classify_bump("1.0.0", "1.0.1"), Bump::Patch);
+        assert_eq!(classify_bump(">=1.0.0", "2.0.0"), Bump::Other);
+
+        let major = update_data("package-a", "^1.0.0", "^2.0.0");
+        assert!(is_writable(&major, false));
+        assert!(!is_writable(&major, true));
+
+        let minor = update_data("package-a", "^1.0.0", "^1.1.0");
+        assert!(is_writable(&minor, true));
+    }
+
+    #[tokio::test]
+    async fn test_catalog_updates_are_held_back_like_sections() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.0.0"}"#),
+            ("/package-b/latest", 200, r#"{"version":"1.1.0"}"#),
+        ])
+        .await;
+        let dir = TestDir::new("catalog-held-back");
+        let path = dir.join("package.json");
+        let path = path.to_str().unwrap();
+        fs::write(path, "{}").unwrap();
+        let catalog = dir.join(PNPM_WORKSPACE_FILE);
+        fs::write(
+            &catalog,
+            "catalog:\n  package-a: ^1.0.0\n  package-b: ^1.0.0\n",
+        )
+        .unwrap();
+
+        let matches = cli()
+            .try_get_matches_from([
+                "ncu-rs",
+                "--registry",
+                &url,
+                "--no-cache",
+                "--catalog",
+                "--no-update-if-same-major",
+                "--fail-on-update",
+                "-u",
+                path,
+            ])
+            .unwrap();
+        let session = Session::new(&matches).unwrap();
+        let exit_code = check_manifest(
+            path,
+            &matches,
+            &session,
+            &CheckOptions::default(),
+            true,
+            true,
+        )
+        .await
+        .unwrap();
+        // The major update of package-a is reported but neither written nor counted as written.
+        assert_eq!(exit_code, UPDATES_AVAILABLE_EXIT_CODE);
+        assert_eq!(
+            fs::read_to_string(&catalog).unwrap(),
+            "catalog:\n  package-a: ^1.0.0\n  package-b: ^1.1.0\n"
+        );
+    }
+
+    #[test]
+    fn test_run_test_command_reverts_on_failure() {
+        let dir = TestDir::new("run-test-command");
+        let path = dir.join("package.json");
+        let path = path.to_str().unwrap();
+
+        let original = r#"{ "dependencies": { "package-a": "^1.0.0" } }"#;
+        let updated = r#"{ "dependencies": { "package-a": "^2.0.0" } }"#;
+
+        let catalog = dir.join(PNPM_WORKSPACE_FILE);
+        let original_catalog = "catalog:\n  package-b: ^1.0.0\n";
+        let originals = [
+            (Path::new(path), original),
+            (catalog.as_path(), original_catalog),
+        ];
+
+        fs::write(path, updated).unwrap();
+        fs::write(&catalog, "catalog:\n  package-b: ^2.0.0\n").unwrap();
+        assert!(run_test_command("test -f package.json", path, &originals).unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), updated);
+
+        assert!(!run_test_command("exit 1", path, &originals).unwrap());
+        assert_eq!(fs::read_to_string(path).unwrap(), original);
+        assert_eq!(fs::read_to_string(&catalog).unwrap(), original_catalog);
+    }
+
+    #[test]
+    fn test_install_command_detection() {
+        let dir = TestDir::new("install-command");
+
+        assert_eq!(install_command(&json!({}), &dir, false), "npm install");
+        fs::write(dir.join("yarn.lock"), "").unwrap();
+        assert_eq!(install_command(&json!({}), &dir, false), "yarn install");
+        assert_eq!(
+            install_command(&json!({ "packageManager": "pnpm@8.6.0" }), &dir, false),
+            "pnpm install"
+        );
+        assert_eq!(
+            install_command(&json!({}), &dir, true),
+            "yarn install --ignore-scripts"
+        );
+    }
+
+    #[test]
+    fn test_lockfile_command_per_package_manager() {
+        let dir = TestDir::new("lockfile-command");
+
+        assert_eq!(
+            lockfile_command(&json!({}), &dir),
+            "npm install --package-lock-only"
+        );
+        assert_eq!(
+            lockfile_command(&json!({ "packageManager": "pnpm@8.6.0" }), &dir),
+            "pnpm install --lockfile-only"
+        );
+        fs::write(dir.join("yarn.lock"), "").unwrap();
+        assert_eq!(lockfile_command(&json!({}), &dir), "yarn install");
+    }
+
+    #[test]
+    fn test_post_write_commands_chain() {
+        let dir = TestDir::new("post-write-commands");
+        let path = dir.join("package.json");
+        let path = path.to_str().unwrap();
+
+        let original = r#"{ "dependencies": { "package-a": "^1.0.0" } }"#;
+        let updated = r#"{ "dependencies": { "package-a": "^2.0.0" } }"#;
+
+        // The install runs before the tests, which can rely on it.
+        fs::write(path, updated).unwrap();
+        let originals = [(Path::new(path), original)];
+        run_post_write_commands(
+            path,
+            &originals,
+            Some("touch installed"),
+            Some("test -f installed"),
+        )
+        .unwrap();
+        assert_eq!(fs::read_to_string(path).unwrap(), updated);
+
+        let err = run_post_write_commands(path, &originals, Some("exit 3"), Some("touch tested"))
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("Install command `exit 3` failed"));
+        assert_eq!(fs::read_to_string(path).unwrap(), original);
+        assert!(!dir.join("tested").exists());
+    }
+
+    #[test]
+    fn test_subcommands_parse_their_args() {
+        let bare = cli()
+            .try_get_matches_from(["ncu-rs", "-u", "--target", "minor", "app/package.json"])
+            .unwrap();
+        assert!(bare.subcommand().is_none());
+        assert!(bare.is_present("update"));
+        assert_eq!(bare.value_of("path"), Some("app/package.json"));
+
+        for name in ["check", "update", "doctor"] {
+            let matches = cli()
+                .try_get_matches_from(["ncu-rs", name, "--target", "minor", "app/package.json"])
+                .unwrap();
+            let (subcommand, matches) = matches.subcommand().unwrap();
+            assert_eq!(subcommand, name);
+            assert_eq!(matches.value_of("target"), Some("minor"));
+            assert_eq!(matches.value_of("path"), Some("app/package.json"));
+        }
+
+        let matches = cli()
+            .try_get_matches_from([
+                "ncu-rs",
+                "query",
+                "react",
+                "--registry",
+                "https://npm.acme.dev/",
+            ])
+            .unwrap();
+        let (subcommand, matches) = matches.subcommand().unwrap();
+        assert_eq!(subcommand, "query");
+        assert_eq!(matches.value_of("package"), Some("react"));
+        assert_eq!(matches.value_of("registry"), Some("https://npm.acme.dev/"));
+        assert!(cli().try_get_matches_from(["ncu-rs", "query"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_check_workspace_in_parallel() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"1.1.0"}"#),
+            ("/package-b/latest", 200, r#"{"version":"2.0.0"}"#),
+        ])
+        .await;
+        let root = TestDir::new("check-workspace");
+        for (dir, dependency) in [("a", "package-a"), ("b", "package-b")] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(
+                root.join(dir).join("package.json"),
+                format!(r#"{{"dependencies": {{"{}": "1.0.0"}}}}"#, dependency),
+            )
+            .unwrap();
+        }
+        let manifests = workspace::find_manifests(&root, 1);
+
+        let matches = cli()
+            .try_get_matches_from([
+                "ncu-rs",
+                "--workspaces",
+                "--parallel-files",
+                "2",
+                "--exit-bitmask",
+                "--no-cache",
+                "--registry",
+                &url,
+            ])
+            .unwrap();
+        let session = Session::new(&matches).unwrap();
+        let result = check_workspace(
+            &manifests,
+            &root,
+            &matches,
+            &session,
+            &CheckOptions::default(),
+            false,
+            2,
+        )
+        .await;
+        // A minor update in one manifest and a major one in the other.
+        assert_eq!(result, (2 | 4, 0));
+
+        fs::write(root.join("b").join("package.json"), "{ not json").unwrap();
+        let result = check_workspace(
+            &manifests,
+            &root,
+            &matches,
+            &session,
+            &CheckOptions::default(),
+            false,
+            2,
+        )
+        .await;
+        assert_eq!(result, (2, 1));
+    }
+
+    #[tokio::test]
+    async fn test_workspace_manifests_share_the_registry() {
+        let url = mock_registry(&[("/package-a/latest", 200, r#"{"version":"1.1.0"}"#)]).await;
+        let root = TestDir::new("shared-registry");
+        for dir in ["a", "b"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(
+                root.join(dir).join("package.json"),
+                r#"{"dependencies": {"package-a": "1.0.0"}}"#,
+            )
+            .unwrap();
+        }
+        let manifests = workspace::find_manifests(&root, 1);
+
+        let matches = cli()
+            .try_get_matches_from(["ncu-rs", "--workspaces", "--no-cache", "--registry", &url])
+            .unwrap();
+        let session = Session::new(&matches).unwrap();
+        let result = check_workspace(
+            &manifests,
+            &root,
+            &matches,
+            &session,
+            &CheckOptions::default(),
+            false,
+            2,
+        )
+        .await;
+        assert_eq!(result, (0, 0));
+        // Both manifests were answered by the one lookup.
+        assert_eq!(session.registry.stats.requests.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn test_workspace_members_query_their_own_publish_registry() {
+        let public = mock_registry(&[("/package-a/latest", 200, r#"{"version":"1.1.0"}"#)]).await;
+        let private = mock_registry(&[("/package-a/latest", 200, r#"{"version":"2.0.0"}"#)]).await;
+        let root = TestDir::new("member-registries");
+        for (dir, registry) in [("a", &public), ("b", &private)] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            let manifest = json!({
+                "publishConfig": { "registry": registry },
+                "dependencies": { "package-a": "1.0.0" }
+            });
+            fs::write(root.join(dir).join("package.json"), manifest.to_string()).unwrap();
+        }
+        let manifests = workspace::find_manifests(&root, 1);
+
+        let matches = cli()
+            .try_get_matches_from(["ncu-rs", "--workspaces", "--no-cache", "-u"])
+            .unwrap();
+        let session = Session::new(&matches).unwrap();
+        let result = check_workspace(
+            &manifests,
+            &root,
+            &matches,
+            &session,
+            &CheckOptions::default(),
+            true,
+            2,
+        )
+        .await;
+        assert_eq!(result, (0, 0));
+
+        for (dir, version) in [("a", "1.1.0"), ("b", "2.0.0")] {
+            let written: Value = serde_json::from_str(
+                &fs::read_to_string(root.join(dir).join("package.json")).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(written[DEP_KEY]["package-a"], version);
+        }
+    }
+
+    #[test]
+    fn test_github_annotation() {
+        assert_eq!(
+            github_annotation(&update_data("react", "^17.0.2", "^18.2.0")),
+            "::warning title=Outdated dependency::react ^17.0.2 => ^18.2.0 (major)"
+        );
+        assert_eq!(
+            github_annotation(&update_data("recoil", "~0.6.0", "~0.7.5")),
+            "::notice title=Outdated dependency::recoil ~0.6.0 => ~0.7.5 (minor)"
+        );
+        assert_eq!(
+            github_annotation(&update_data("lodash", "4.17.20", "4.17.21")),
+            "::notice title=Outdated dependency::lodash 4.17.20 => 4.17.21 (patch)"
+        );
+    }
+
+    #[test]
+    fn test_parse_excluded_version() {
+        assert_eq!(
+            parse_excluded_version("@scope/package-a@1.2.3").unwrap(),
+            ("@scope/package-a".to_string(), "1.2.3".to_string())
+        );
+        assert!(parse_excluded_version("@scope/package-a").is_err());
+        assert!(parse_excluded_version("package-a@").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_excluded_latest_selects_prior_version() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.1.0"}"#),
+            ("/package-a/2.0.0", 200, r#"{"version":"2.0.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{"versions":{"1.0.0":{},"2.0.0":{},"2.1.0":{},"2.2.0-beta.1":{}}}"#,
+            ),
+        ])
+        .await;
+        let registry = registry_at(url);
+        let options = Arc::new(CheckOptions {
+            excluded_versions: vec![("package-a".to_string(), "2.1.0".to_string())],
+            ..Default::default()
+        });
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+
+        let checks_vec = check_all(&registry, &options, &deps).await;
+
+        match &checks_vec[0] {
+            PackageCheck::Update(update) => assert_eq!(update.new_version, "^2.0.0"),
+            other => panic!("Expected an update, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deprecated_target_fails_gate() {
+        let url = mock_registry(&[
+            (
+                "/package-a/latest",
+                200,
+                r#"{"version":"2.0.0","deprecated":"use package-b instead"}"#,
+            ),
+            ("/package-b/latest", 200, r#"{"version":"1.0.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+        deps.insert("package-b".to_string(), "^0.9.0".to_string());
+
+        let checks_vec = check_all(&registry, &Default::default(), &deps).await;
+
+        let err = deprecation_gate(checks_vec.iter()).unwrap_err().to_string();
+        assert!(err.contains("package-a@^2.0.0: use package-b instead"));
+        assert!(!err.contains("package-b@"));
+        assert!(deprecation_gate(checks_vec[1..].iter()).is_ok());
+    }
+
+    #[test]
+    fn test_jsonc_manifest_keeps_comments_after_bump() {
+        let contents = r#"{
+  // Runtime dependencies
+  "dependencies": {
+    "react": "^17.0.0" /* keep in sync with react-dom */
+  },
+  "devDependencies": {}
+}"#;
+        let package_json: Value = serde_json::from_str(&jsonc::strip_comments(contents)).unwrap();
+        let mut deps = parse_dependency_map(DEP_KEY, &package_json[DEP_KEY]).unwrap();
+        deps.insert("react".to_string(), "^18.2.0".to_string());
+
+        let edited = edit_manifest_versions(contents, &package_json, &[(DEP_KEY, &deps)]).unwrap();
+
+        assert!(edited.contains("// Runtime dependencies"));
+        assert!(edited.contains(r#""react": "^18.2.0" /* keep in sync with react-dom */"#));
+    }
+
+    #[test]
+    fn test_updates_json_shape() {
+        let mut dev_update = update_data("package-b", "~1.2.0", "~1.3.0");
+        dev_update.section = DEV_DEP_KEY;
+        let checks = vec![
+            PackageCheck::Update(update_data("package-a", "^1.0.0", "^2.0.0")),
+            PackageCheck::Skipped {
+                package_name: "package-c".to_string(),
+                reason: SkipReason::AlreadyLatest,
+            },
+            PackageCheck::Update(dev_update),
+            PackageCheck::Skipped {
+                package_name: "package-d".to_string(),
+                reason: SkipReason::Rejected,
+            },
+        ];
+
+        assert_eq!(
+            serde_json::to_string(&updates_json(&checks, &[])).unwrap(),
+            r#"{"updates":[{"name":"package-a","current":"^1.0.0","latest":"^2.0.0"},{"name":"package-b","current":"~1.2.0","latest":"~1.3.0"}],"errors":[],"skipped":[{"name":"package-c","reason":"up-to-date"},{"name":"package-d","reason":"rejected"}],"catalogs":{}}"#
+        );
+    }
+
+    /// Infers the JSON Schema of every value found at `path` in the sample reports: the fields
+    /// of an object are required when every sample has them. Objects at a path ending in one of
+    /// `maps` are keyed by name rather than by field (with `true`, by one of the names seen), and
+    /// strings at a path ending in one of `enums` can only be one of the strings seen.
+    fn infer_schema(
+        samples: &[&Value],
+        path: &str,
+        maps: &[(&str, bool)],
+        enums: &[&str],
+    ) -> Value {
+        let mut seen: Vec<Value> = vec![];
+        if enums.iter().any(|suffix| path.ends_with(suffix)) {
+            for sample in samples {
+                if !seen.contains(sample) {
+                    seen.push((*sample).clone());
+                }
+            }
+            return json!({ "enum": seen });
+        }
+
+        if samples.iter().all(|sample| sample.is_array()) {
+            let items: Vec<&Value> = samples
+                .iter()
+                .flat_map(|sample| sample.as_array().unwrap())
+                .collect();
+            return json!({ "type": "array", "items": infer_schema(&items, path, maps, enums) });
+        }
+
+        if samples.iter().all(|sample| sample.is_object()) {
+            let objects: Vec<&serde_json::Map<String, Value>> = samples
+                .iter()
+                .map(|sample| sample.as_object().unwrap())
+                .collect();
+            let mut keys: Vec<&String> = vec![];
+            for key in objects.iter().flat_map(|object| object.keys()) {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+
+            if let Some((_, named_keys)) = maps.iter().find(|(suffix, _)| path.ends_with(suffix)) {
+                let values: Vec<&Value> =
+                    objects.iter().flat_map(|object| object.values()).collect();
+                let mut schema = json!({
+                    "type": "object",
+                    "additionalProperties": infer_schema(&values, &format!("{}/*", path), maps, enums),
+                });
+                if *named_keys {
+                    schema["propertyNames"] = json!({ "enum": keys });
+                }
+                return schema;
+            }
+
+            let required: Vec<&String> = keys
+                .iter()
+                .copied()
+                .filter(|key| objects.iter().all(|object| object.contains_key(*key)))
+                .collect();
+            let properties: serde_json::Map<String, Value> = keys
+                .iter()
+                .map(|key| {
+                    let values: Vec<&Value> = objects
+                        .iter()
+                        .filter_map(|object| object.get(*key))
+                        .collect();
+                    let path = format!("{}/{}", path, key);
+                    (key.to_string(), infer_schema(&values, &path, maps, enums))
+                })
+                .collect();
+            return json!({
+                "type": "object",
+                "required": required,
+                "additionalProperties": false,
+                "properties": properties,
+            });
+        }
+
+        for sample in samples {
+            let kind = match sample {
+                Value::String(_) => json!("string"),
+                Value::Null => json!("null"),
+                other => panic!("No schema type for {}", other),
+            };
+            if !seen.contains(&kind) {
+                seen.push(kind);
+            }
+        }
+        match seen.as_slice() {
+            [kind] => json!({ "type": kind }),
+            kinds => json!({ "type": kinds }),
+        }
+    }
+
+    /// The parts of a schema that `infer_schema` can know, with every `$ref` into `root` resolved.
+    fn schema_shape(schema: &Value, root: &Value) -> Value {
+        match schema {
+            Value::Object(object) => {
+                if let Some(Value::String(reference)) = object.get("$ref") {
+                    let pointer = reference.trim_start_matches('#');
+                    return schema_shape(root.pointer(pointer).unwrap(), root);
+                }
+                object
+                    .iter()
+                    .filter(|(key, _)| !["$schema", "title", "description"].contains(&key.as_str()))
+                    .map(|(key, value)| (key.clone(), schema_shape(value, root)))
+                    .collect::<serde_json::Map<_, _>>()
+                    .into()
+            }
+            Value::Array(items) => items.iter().map(|item| schema_shape(item, root)).collect(),
+            other => other.clone(),
+        }
+    }
+
+    #[test]
+    fn test_report_schema_matches_updates_json() {
+        // Listed by hand, but a new variant won't compile until it's added to them.
+        let reasons = || {
+            [
+                SkipReason::GitDependency,
+                SkipReason::AlreadyLatest,
+                SkipReason::RangeSatisfiable,
+                SkipReason::RangeOperator,
+                SkipReason::PrereleaseOnly,
+                SkipReason::UnsupportedSpec,
+                SkipReason::OtherPlatform,
+                SkipReason::RecentEnough,
+                SkipReason::Rejected,
+            ]
+        };
+        for reason in &reasons() {
+            match reason {
+                SkipReason::GitDependency
+                | SkipReason::AlreadyLatest
+                | SkipReason::RangeSatisfiable
+                | SkipReason::RangeOperator
+                | SkipReason::PrereleaseOnly
+                | SkipReason::UnsupportedSpec
+                | SkipReason::OtherPlatform
+                | SkipReason::RecentEnough
+                | SkipReason::Rejected => {}
+            }
+        }
+        let targets = [Target::Latest, Target::Minor, Target::Patch];
+        for target in &targets {
+            match target {
+                Target::Latest | Target::Minor | Target::Patch => {}
+            }
+        }
+
+        // Every field of the report, each optional one both present and missing.
+        let checks = || {
+            let mut checks = vec![
+                PackageCheck::Update(update_data("package-a", "^1.0.0", "^2.0.0")),
+                PackageCheck::Update(PackageUpdateData {
+                    wanted: Some("1.4.0".to_string()),
+                    compared: targets
+                        .iter()
+                        .map(|target| {
+                            (
+                                *target,
+                                (*target != Target::Patch).then(|| "1.4.0".to_string()),
+                            )
+                        })
+                        .collect(),
+                    ..update_data("package-b", "^1.0.0", "^2.0.0")
+                }),
+                PackageCheck::Failed {
+                    package_name: "package-c".to_string(),
+                    error: "Not found".to_string(),
+                },
+            ];
+            checks.extend(reasons().into_iter().map(|reason| PackageCheck::Skipped {
+                package_name: "package-d".to_string(),
+                reason,
+            }));
+            checks
+        };
+        let report = updates_json(&checks(), &[(Some("react17".to_string()), checks())]);
+
+        let generated = infer_schema(
+            &[&report],
+            "",
+            &[("/catalogs", false), ("/targets", true)],
+            &["/reason"],
+        );
+        let schema: Value = serde_json::from_str(REPORT_SCHEMA).unwrap();
+        assert_eq!(generated, schema_shape(&schema, &schema));
+    }
+
+    #[test]
+    fn test_updates_json_when_up_to_date() {
+        let checks = vec![PackageCheck::Skipped {
+            package_name: "package-a".to_string(),
+            reason: SkipReason::AlreadyLatest,
+        }];
+
+        let output = serde_json::to_string(&updates_json(&checks, &[])).unwrap();
+        let parsed: Value = serde_json::from_str(&output).unwrap();
+        assert_eq!(parsed["updates"], json!([]));
+        assert_eq!(parsed["errors"], json!([]));
+        assert_eq!(
+            parsed["skipped"],
+            json!([{ "name": "package-a", "reason": "up-to-date" }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_git_dependency_is_listed_as_skipped() {
+        let registry = Arc::new(Registry::new(
+            Client::new(),
+            "http://127.0.0.1:9/".to_string(),
+            None,
+        ));
+        let options = Arc::new(CheckOptions::default());
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert(
+            "package-a".to_string(),
+            "git+https://github.com/acme/package-a.git".to_string(),
+        );
+
+        let checks_vec = check_all(&registry, &options, &deps).await;
+
+        assert_eq!(
+            updates_json(&checks_vec, &[])["skipped"],
+            json!([{ "name": "package-a", "reason": "git-dependency" }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deprecated_current_versions_are_reported() {
+        let url = mock_registry(&[
+            (
+                "/request/2.88.2",
+                200,
+                r#"{"version":"2.88.2","deprecated":"request has been deprecated"}"#,
+            ),
+            ("/react/18.2.0", 200, r#"{"version":"18.2.0"}"#),
+        ])
+        .await;
+        let registry = Registry::new(Client::new(), url, None);
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("react".to_string(), "^18.2.0".to_string());
+        deps.insert("widget".to_string(), "github:acme/widget".to_string());
+        let mut dev_deps: IndexMap<String, String> = IndexMap::new();
+        dev_deps.insert("request".to_string(), "2.88.2".to_string());
+
+        assert_eq!(
+            deprecated_current(&registry, &Default::default(), &[&deps, &dev_deps], false).await,
+            ["request@2.88.2 is deprecated: request has been deprecated"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_checks_keep_manifest_order() {
+        // The first packages take the longest to check, so they finish last.
+        let names = ["package-a", "package-b", "package-c", "package-d"];
+        let futures: Vec<JoinHandle<(PackageCheck, Vec<String>)>> = names
+            .iter()
+            .enumerate()
+            .map(|(index, name)| {
+                let package_name = name.to_string();
+                tokio::spawn(async move {
+                    let delay = (names.len() - index) as u64 * 20;
+                    tokio::time::sleep(std::time::Duration::from_millis(delay)).await;
+                    let check = PackageCheck::Skipped {
+                        package_name,
+                        reason: SkipReason::AlreadyLatest,
+                    };
+                    (check, vec![])
+                })
+            })
+            .collect();
+
+        let mut pb = quiet_progress_bar(4);
+        let mut checks_vec: Vec<PackageCheck> = vec![];
+        await_futures(futures, &mut pb, &mut checks_vec)
+            .await
+            .unwrap();
+
+        let order: Vec<&str> = checks_vec.iter().map(PackageCheck::package_name).collect();
+        assert_eq!(order, names);
+    }
+
+    #[tokio::test]
+    async fn test_low_download_warnings() {
+        let url = mock_registry(&[
+            (
+                "/downloads/point/last-week/package-a",
+                200,
+                r#"{"downloads":12,"package":"package-a"}"#,
+            ),
+            (
+                "/downloads/point/last-week/package-b",
+                200,
+                r#"{"downloads":50000,"package":"package-b"}"#,
+            ),
+        ])
+        .await;
+        // Looked up by the name the version came from, not the resolution key or alias.
+        let checks = vec![
+            PackageCheck::Update(PackageUpdateData {
+                query_name: "package-a".to_string(),
+                ..update_data("**/package-a", "^1.0.0", "^2.0.0")
+            }),
+            PackageCheck::Update(PackageUpdateData {
+                query_name: "package-b".to_string(),
+                ..update_data("b", "npm:package-b@^1.0.0", "npm:package-b@^1.1.0")
+            }),
+        ];
+
+        let registry = Registry::new(Client::new(), url.clone(), None).with_concurrency(1);
+        let client = Client::new();
+        assert_eq!(
+            low_download_warnings(&registry, &client, &url, &checks, 1000, false).await,
+            ["**/package-a has only 12 weekly downloads (fewer than 1000)"]
+        );
+
+        // With every permit taken, the lookups wait their turn.
+        let _permit = registry.acquire().await;
+        let lookups = low_download_warnings(&registry, &client, &url, &checks, 1000, false);
+        assert!(tokio::time::timeout(Duration::from_millis(50), lookups)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_summary_json_sidecar_counts() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.0.0"}"#),
+            ("/package-b/latest", 200, r#"{"version":"1.1.0"}"#),
+            ("/package-c/latest", 200, r#"{"version":"1.0.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+        deps.insert("package-b".to_string(), "^1.0.0".to_string());
+        deps.insert("package-c".to_string(), "^1.0.0".to_string());
+        deps.insert("package-d".to_string(), "^1.0.0".to_string());
+
+        let checks_vec = check_all(&registry, &Default::default(), &deps).await;
+
+        let dir = TestDir::new("summary");
+        let path = dir.join("summary.json");
+        fs::write(
+            &path,
+            serde_json::to_string_pretty(&summary_json(checks_vec.iter())).unwrap(),
+        )
+        .unwrap();
+        let summary: Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+
+        assert_eq!(summary["checked"], 4);
+        assert_eq!(summary["updates"], 2);
+        assert_eq!(summary["skipped"], 1);
+        assert_eq!(summary["failed"], 1);
+        assert_eq!(summary["bumps"]["major"], 1);
+        assert_eq!(summary["bumps"]["minor"], 1);
+        assert_eq!(summary["bumps"]["patch"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_range_operator_policy() {
+        let url = mock_registry(&[("/package-a/latest", 200, r#"{"version":"2.0.0"}"#)]).await;
+        let registry = registry_at(url);
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), ">=1.0.0".to_string());
+
+        let checks_vec = check_all(&registry, &Default::default(), &deps).await;
+        match &checks_vec[0] {
+            PackageCheck::Update(update) => assert_eq!(update.new_version, ">=2.0.0"),
+            other => panic!("Expected an update, got {:?}", other),
+        }
+
+        let options = Arc::new(CheckOptions {
+            leave_range_operators: true,
+            ..Default::default()
+        });
+        let checks_vec = check_all(&registry, &options, &deps).await;
+        assert!(matches!(
+            checks_vec[0],
+            PackageCheck::Skipped {
+                reason: SkipReason::RangeOperator,
+                ..
+            }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_repository_links_for_outdated_packages() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.0.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{"repository":{"type":"git","url":"git+https://github.com/acme/package-a.git"}}"#,
+            ),
+            ("/package-b/latest", 200, r#"{"version":"1.0.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url.clone());
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+        deps.insert("package-b".to_string(), "^1.0.0".to_string());
+
+        let checks_vec = check_all(&registry, &Default::default(), &deps).await;
+
+        assert_eq!(
+            repository_links(&registry, &checks_vec).await,
+            vec![(
+                "package-a".to_string(),
+                Some("https://github.com/acme/package-a".to_string())
+            )]
+        );
+
+        // With every permit taken, the lookups wait their turn.
+        let registry = Registry::new(Client::new(), url, None).with_concurrency(1);
+        let _permit = registry.acquire().await;
+        let lookups = repository_links(&registry, &checks_vec);
+        assert!(tokio::time::timeout(Duration::from_millis(50), lookups)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_release_notes_look_up_only_the_first_updates() {
+        let url = mock_registry(&[
+            (
+                "/package-a",
+                200,
+                r#"{"repository":"https://github.com/acme/package-a"}"#,
+            ),
+            (
+                "/repos/acme/package-a/releases?per_page=20",
+                200,
+                r#"[{"tag_name": "v2.0.0", "body": "New API"}]"#,
+            ),
+        ])
+        .await;
+        let registry = registry_at(url.clone());
+        let checks = vec![
+            PackageCheck::Update(update_data("package-a", "^1.0.0", "^2.0.0")),
+            PackageCheck::Update(update_data("package-b", "^1.0.0", "^2.0.0")),
+        ];
+
+        assert_eq!(
+            fetch_release_notes(&registry, &Client::new(), &url, &checks, 1, false).await,
+            vec![(
+                "package-a".to_string(),
+                "2.0.0".to_string(),
+                "New API".to_string()
+            )]
+        );
+        // Only package-a's packument is fetched, package-b is past the count.
+        assert_eq!(registry.stats.requests.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_updatable_names() {
+        let checks = [
+            PackageCheck::Update(update_data("react", "^17.0.0", "^18.2.0")),
+            PackageCheck::Skipped {
+                package_name: "left-pad".to_string(),
+                reason: SkipReason::AlreadyLatest,
+            },
+            PackageCheck::Update(update_data("@types/node", "^18.0.0", "^20.0.0")),
+        ];
+
+        assert_eq!(updatable_names(checks.iter()), "react @types/node");
+    }
+
+    #[test]
+    fn test_rewrite_spec() {
+        let rules = parse_rewrite_rules("~=^, workspace:*=workspace:^").unwrap();
+
+        assert_eq!(rewrite_spec("~1.2.3", &rules).as_deref(), Some("^1.2.3"));
+        assert_eq!(
+            rewrite_spec("workspace:*", &rules).as_deref(),
+            Some("workspace:^")
+        );
+        for spec in ["^2.0.0", "workspace:~1.0.0", "github:acme/widget", "1.0.0"] {
+            assert_eq!(rewrite_spec(spec, &rules), None, "{} should be kept", spec);
+        }
+
+        assert!(parse_rewrite_rules("~^").is_err());
+        assert!(parse_rewrite_rules("=^").is_err());
+    }
+
+    #[test]
+    fn test_validate_spec() {
+        for spec in [
+            "^1.2.3",
+            "~0.1.0",
+            ">= 1.0.0 < 2",
+            "1.x",
+            "*",
+            "",
+            "1.0.0 - 2.0.0",
+            "^1.0.0 || ^2.0.0",
+            "v1.2.3",
+            "latest",
+            "npm:react@^18.0.0",
+            "workspace:*",
+            "github:acme/widget",
+        ] {
+            assert!(validate_spec(spec).is_ok(), "{} should be valid", spec);
+        }
+
+        for spec in ["^1.2.3.4", "1.2.3.4", ">=", "~>1.0"] {
+            assert!(validate_spec(spec).is_err(), "{} should be invalid", spec);
+        }
+    }
+
+    #[test]
+    fn test_validate_manifest_points_at_bad_entry() {
+        let package_json = serde_json::json!({
+            "dependencies": { "react": "^18.2.0", "recoil": "^0.7.0.1" },
+            "devDependencies": { "typescript": 5 },
+        });
+
+        let problems = validate_manifest(&package_json);
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("dependencies.recoil: \"^0.7.0.1\" ("));
+        assert_eq!(
+            problems[1],
+            "devDependencies.typescript: 5 (expected a string, found 5)"
+        );
+    }
+
+    #[test]
+    fn test_sort_moves() {
+        let package_json = serde_json::json!({
+            "dependencies": { "react": "^18.2.0", "@babel/core": "^7.0.0", "lodash": "^4.17.21" },
+            "devDependencies": { "eslint": "^8.0.0", "prettier": "^3.0.0" },
+        });
+
+        assert_eq!(
+            sort_moves(&package_json),
+            vec![
+                "dependencies.react: 1 => 3",
+                "dependencies.@babel/core: 2 => 1",
+                "dependencies.lodash: 3 => 2",
+            ]
+        );
+        assert!(
+            sort_moves(&serde_json::json!({ "dependencies": { "a": "1", "b": "1" } })).is_empty()
+        );
+    }
+
+    #[test]
+    fn test_iso_timestamp() {
+        assert_eq!(iso_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(iso_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(iso_timestamp(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+
+    #[tokio::test]
+    async fn test_before_and_min_age_select_jointly() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.0.0"}"#),
+            ("/package-a/1.1.0", 200, r#"{"version":"1.1.0"}"#),
+            ("/package-a/1.2.0", 200, r#"{"version":"1.2.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{
+                    "versions": {"1.0.0": {}, "1.1.0": {}, "1.2.0": {}, "2.0.0": {}},
+                    "time": {
+                        "1.0.0": "2020-01-01T00:00:00.000Z",
+                        "1.1.0": "2021-06-01T00:00:00.000Z",
+                        "1.2.0": "2023-01-01T00:00:00.000Z",
+                        "2.0.0": "2999-01-01T00:00:00.000Z"
+                    }
+                }"#,
+            ),
+        ])
+        .await;
+        let registry = registry_at(url);
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+
+        // The min-age cutoff (now) is earlier than the date, so 2.0.0 is still too new.
+        // Then the date is the earlier cutoff, which also rules out 1.2.0.
+        for (before, expected) in [("2999-06-01", "^1.2.0"), ("2022-01-01", "^1.1.0")] {
+            let options = Arc::new(CheckOptions {
+                published_before: publish_cutoff(Some(before), Some(0)).unwrap(),
+                ..Default::default()
+            });
+            let checks_vec = check_all(&registry, &options, &deps).await;
+
+            match &checks_vec[0] {
+                PackageCheck::Update(update) => assert_eq!(update.new_version, expected),
+                other => panic!("Expected an update, got {:?}", other),
+            }
+        }
+
+        assert!(publish_cutoff(Some("01/02/2022"), None).is_err());
+    }
+
+    #[test]
+    fn test_exit_bitmask() {
+        let checks = [
+            PackageCheck::Update(update_data("react", "^17.0.0", "^18.2.0")),
+            PackageCheck::Update(update_data("recoil", "^0.6.0", "^0.7.0")),
+            PackageCheck::Skipped {
+                package_name: "left-pad".to_string(),
+                reason: SkipReason::AlreadyLatest,
+            },
+        ];
+
+        assert_eq!(exit_bitmask(checks.iter()), 6);
+        assert_eq!(exit_bitmask(checks[2..].iter()), 0);
+    }
+
+    #[test]
+    fn test_confirm_update() {
+        let update = update_data("package-a", "^1.0.0", "^2.0.0");
+        let answers = [("n\n", false), ("No\n", false), ("y\n", true), ("\n", true)];
+        for (answer, expected) in answers {
+            let mut output = vec![];
+            assert_eq!(
+                confirm_update(&mut answer.as_bytes(), &mut output, &update).unwrap(),
+                expected
+            );
+            assert_eq!(
+                String::from_utf8(output).unwrap(),
+                "    write package-a ^1.0.0 => ^2.0.0? [Y/n] "
+            );
+        }
+    }
+
+    #[test]
+    fn test_parse_package_ttl() {
+        assert_eq!(
+            parse_package_ttl("@types/node=60").unwrap(),
+            ("@types/node".to_string(), Duration::from_secs(3600))
+        );
+        assert!(parse_package_ttl("react").is_err());
+        assert!(parse_package_ttl("react=soon").is_err());
+        assert!(parse_package_ttl("=5").is_err());
+    }
+
+    #[test]
+    fn test_fail_on_update_code() {
+        assert_eq!(fail_on_update_code(2, 0), UPDATES_AVAILABLE_EXIT_CODE);
+        assert_eq!(fail_on_update_code(2, 1), UPDATES_AVAILABLE_EXIT_CODE);
+        assert_eq!(fail_on_update_code(2, 2), 0);
+        assert_eq!(fail_on_update_code(0, 0), 0);
+    }
+
+    #[tokio::test]
+    async fn test_policy_url_constrains_allowed_versions() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"3.0.0"}"#),
+            ("/package-a/2.1.0", 200, r#"{"version":"2.1.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{"versions":{"1.0.0":{},"2.0.0":{},"2.1.0":{},"3.0.0":{}}}"#,
+            ),
+            ("/package-b/latest", 200, r#"{"version":"5.0.0"}"#),
+            (
+                "/policy.json",
+                200,
+                r#"{"package-a":["1.0.0","2.0.0","2.1.0"]}"#,
+            ),
+        ])
+        .await;
+        let registry = registry_at(url.clone());
+        let options = Arc::new(CheckOptions {
+            allowed_versions: registry
+                .version_policy(&format!("{}/policy.json", url))
+                .await
+                .unwrap(),
+            ..Default::default()
+        });
+
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+        deps.insert("package-b".to_string(), "^4.0.0".to_string());
+
+        let checks_vec = check_all(&registry, &options, &deps).await;
+
+        let new_versions: Vec<&str> = checks_vec
+            .iter()
+            .map(|check| match check {
+                PackageCheck::Update(update) => update.new_version.as_str(),
+                other => panic!("Expected an update, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(new_versions, vec!["^2.1.0", "^5.0.0"]);
+    }
 
-    let path = matches.value_of("path").unwrap_or("package.json");
-    let should_update = matches.is_present("update");
+    #[tokio::test]
+    async fn test_json_progress_event_per_package() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.0.0"}"#),
+            ("/package-b/latest", 200, r#"{"version":"1.0.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
 
-    let package_file_contents = fs::read_to_string(&path)?;
-    let mut package_json: serde_json::Value = serde_json::from_str(&package_file_contents)?;
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+        deps.insert("package-b".to_string(), "^1.0.0".to_string());
+        deps.insert("package-c".to_string(), "^1.0.0".to_string());
 
-    let deps = package_json.get(DEP_KEY).unwrap();
-    let dev_deps = package_json.get(DEV_DEP_KEY).unwrap();
+        let dir = TestDir::new("progress");
+        let path = dir.join("progress.jsonl");
+        let mut progress = Progress::Json {
+            done: 0,
+            total: 3,
+            out: Box::new(fs::File::create(&path).unwrap()),
+        };
+        let futures = process_dependencies(&registry, &Default::default(), &deps, DEP_KEY).await;
+        let mut checks_vec: Vec<PackageCheck> = vec![];
+        await_futures(futures, &mut progress, &mut checks_vec)
+            .await
+            .unwrap();
+        drop(progress);
 
-    let mut deps: IndexMap<String, String> = serde_json::from_value(deps.clone())?;
-    let mut dev_deps: IndexMap<String, String> = serde_json::from_value(dev_deps.clone())?;
+        let events: Vec<Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
 
-    let dep_count = (deps.len() + dev_deps.len()) as u64;
+        assert_eq!(events.len(), deps.len());
+        assert_eq!(events[2]["done"], 3);
+        assert_eq!(events[2]["total"], 3);
+        let mut packages: Vec<&str> = events
+            .iter()
+            .map(|event| event["package"].as_str().unwrap())
+            .collect();
+        packages.sort();
+        assert_eq!(packages, vec!["package-a", "package-b", "package-c"]);
+    }
 
-    let dep_futures = process_dependencies(&deps, false).await;
-    let dev_dep_futures = process_dependencies(&dev_deps, true).await;
+    #[tokio::test]
+    async fn test_progress_follows_completion_order() {
+        let check = |package_name: &str| PackageCheck::Skipped {
+            package_name: package_name.to_string(),
+            reason: SkipReason::AlreadyLatest,
+        };
+        let (slow, fast) = (check("slow"), check("fast"));
+        let futures = vec![
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                (slow, vec!["slow warning".to_string()])
+            }),
+            tokio::spawn(async move { (fast, vec!["fast warning".to_string()]) }),
+        ];
 
-    let mut updates = vec![];
-    let mut pb = ProgressBar::new(dep_count);
+        let dir = TestDir::new("progress-order");
+        let path = dir.join("progress.jsonl");
+        let mut progress = Progress::Json {
+            done: 0,
+            total: 2,
+            out: Box::new(fs::File::create(&path).unwrap()),
+        };
+        let mut checks_vec: Vec<PackageCheck> = vec![];
+        let warnings = await_futures(futures, &mut progress, &mut checks_vec)
+            .await
+            .unwrap();
+        drop(progress);
 
-    pb.show_speed = false;
-    pb.show_time_left = false;
+        let events: Vec<Value> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events[0]["package"], "fast");
+        assert_eq!(events[1]["package"], "slow");
+        // The checks themselves still come back in manifest order.
+        let names: Vec<&str> = checks_vec.iter().map(PackageCheck::package_name).collect();
+        assert_eq!(names, vec!["slow", "fast"]);
+        assert_eq!(warnings, vec!["slow warning", "fast warning"]);
+    }
 
-    await_futures(dep_futures, &mut pb, &mut updates).await?;
-    await_futures(dev_dep_futures, &mut pb, &mut updates).await?;
+    #[tokio::test]
+    async fn test_prerelease_only_bumps_are_skipped() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"1.0.0-rc.2"}"#),
+            ("/package-b/latest", 200, r#"{"version":"1.0.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
 
-    let mut did_update_packages = false;
-    for update in updates {
-        did_update_packages = true;
-        println!(
-            "{}     {} => {}",
-            update.package_name, update.old_version, update.new_version
-        );
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "1.0.0-rc.1".to_string());
+        deps.insert("package-b".to_string(), "1.0.0-rc.1".to_string());
 
-        // If we should update the package.json file, update the relevant map.
-        if should_update {
-            if update.dev {
-                dev_deps.insert(update.package_name, update.new_version);
-            } else {
-                deps.insert(update.package_name, update.new_version);
+        let checks_vec = check_all(&registry, &Default::default(), &deps).await;
+
+        assert!(matches!(
+            checks_vec[0],
+            PackageCheck::Skipped {
+                reason: SkipReason::PrereleaseOnly,
+                ..
             }
+        ));
+        match &checks_vec[1] {
+            PackageCheck::Update(update) => assert_eq!(update.new_version, "1.0.0"),
+            other => panic!("Expected an update, got {:?}", other),
         }
     }
 
-    // Finally, merge the newly updated versions into the previous value struct.
-    if should_update {
-        insert_new_maps(&mut package_json, deps, dev_deps)?;
+    #[test]
+    fn test_backup_matches_original_after_write() {
+        let dir = TestDir::new("backup");
+        let path = dir.join("package.json");
+        let path = path.to_str().unwrap();
+        let original = r#"{"dependencies": {"react": "^17.0.0"}}"#;
+        fs::write(path, original).unwrap();
 
-        // Write the updated package.json file.
-        let package_file_contents = serde_json::to_string_pretty(&package_json)?;
-        fs::write(&path, package_file_contents)?;
+        let backup_path = write_backup(path, &fs::read_to_string(path).unwrap()).unwrap();
+        fs::write(path, r#"{"dependencies": {"react": "^18.2.0"}}"#).unwrap();
 
-        if did_update_packages {
-            println!(
-                "Updated {}. Please install the updated packages. (npm/yarn/pnpm install)!",
-                path
-            );
-        } else {
-            println!("No dependency updates found.");
+        assert_eq!(backup_path, format!("{}.bak", path));
+        assert_eq!(fs::read_to_string(&backup_path).unwrap(), original);
+    }
+
+    #[test]
+    fn test_bundled_dependency_drift() {
+        let dir = TestDir::new("bundled-drift");
+        for (package_name, version) in [("left-pad", "1.1.0"), ("is-odd", "3.0.1")] {
+            let package_dir = dir.join("node_modules").join(package_name);
+            fs::create_dir_all(&package_dir).unwrap();
+            fs::write(
+                package_dir.join("package.json"),
+                format!(r#"{{ "version": "{}" }}"#, version),
+            )
+            .unwrap();
         }
+
+        let package_json = serde_json::json!({
+            "dependencies": { "left-pad": "^1.3.0", "is-odd": "^3.0.0" },
+            "bundledDependencies": ["left-pad", "is-odd", "is-even"],
+        });
+        let deps = parse_dependency_map(DEP_KEY, &package_json[DEP_KEY]).unwrap();
+
+        assert_eq!(
+            bundled_drift(&package_json, &deps, &dir),
+            vec![
+                "bundled left-pad is 1.1.0, which doesn't match its declared ^1.3.0",
+                "is-even is bundled but not declared in \"dependencies\"",
+            ]
+        );
     }
 
-    let end = Instant::now();
-    println!(
-        "Operation completed, duration: {:#.2?}",
-        end.duration_since(start)
-    );
+    #[test]
+    fn test_trusted_dependencies() {
+        let package_json = serde_json::json!({
+            "dependencies": { "esbuild": "^0.19.0" },
+            "devDependencies": { "husky": "^8.0.0" },
+            "trustedDependencies": ["esbuild", "husky", "sharp"],
+        });
+        assert!(validate_manifest(&package_json).is_empty());
+        let deps = dependency_section(&package_json, DEP_KEY).unwrap();
+        let dev_deps = dependency_section(&package_json, DEV_DEP_KEY).unwrap();
 
-    Ok(())
-}
+        assert_eq!(
+            unknown_trusted_dependencies(&package_json, &[&deps, &dev_deps]),
+            vec!["sharp is trusted but isn't a direct dependency"]
+        );
+    }
 
-/// Helper function to await all dep futures and update the progress bar according to progress.
-async fn await_futures(
-    futures: Vec<JoinHandle<Option<PackageUpdateData>>>,
-    progress_bar: &mut ProgressBar<Stdout>,
-    updates_vec: &mut Vec<PackageUpdateData>,
-) -> Result<(), Error> {
-    for future in futures {
-        progress_bar.inc();
-        let val = future.await?;
-        if let Some(update) = val {
-            updates_vec.push(update);
-        }
+    #[test]
+    fn test_epoch_day() {
+        assert_eq!(epoch_day("1970-01-01T00:00:00.000Z"), Some(0));
+        assert_eq!(
+            epoch_day(&iso_timestamp(1_700_000_000)),
+            Some(1_700_000_000 / 86400)
+        );
+        assert_eq!(
+            epoch_day("2024-03-01"),
+            epoch_day("2024-02-28").map(|day| day + 2)
+        );
+        assert_eq!(epoch_day("not a date"), None);
     }
-    Ok(())
-}
 
-/// Processes all dependencies in the given map. Returns a Vec containing a JoinHandle to the task
-/// for each dependency.
-async fn process_dependencies(
-    deps: &IndexMap<String, String>,
-    dev: bool,
-) -> Vec<tokio::task::JoinHandle<Option<PackageUpdateData>>> {
-    let futures: Vec<_> = deps
-        .iter()
-        .map(
-            |(package_name, version)| -> tokio::task::JoinHandle<Option<PackageUpdateData>> {
-                let package_name = package_name.clone();
-                let version = version.clone();
+    #[tokio::test]
+    async fn test_behind_by_reports_only_neglected_packages() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.0.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{"versions":{"1.0.0":{},"2.0.0":{}},"time":{"1.0.0":"2022-01-01T00:00:00.000Z","2.0.0":"2023-02-05T00:00:00.000Z"}}"#,
+            ),
+            ("/package-b/latest", 200, r#"{"version":"1.1.0"}"#),
+            (
+                "/package-b",
+                200,
+                r#"{"versions":{"1.0.0":{},"1.1.0":{}},"time":{"1.0.0":"2023-01-01T00:00:00.000Z","1.1.0":"2023-03-01T00:00:00.000Z"}}"#,
+            ),
+        ])
+        .await;
+        let registry = registry_at(url);
+        let options = Arc::new(CheckOptions {
+            behind_by_days: Some(365),
+            ..Default::default()
+        });
 
-                tokio::spawn(async move {
-                    let cmp_ver = version.replace('^', "").replace('~', "");
-                    let ver_prefix = if version.contains('^') {
-                        "^"
-                    } else if version.contains('~') {
-                        "~"
-                    } else {
-                        ""
-                    };
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+        deps.insert("package-b".to_string(), "^1.0.0".to_string());
 
-                    match get_package_version(&package_name).await {
-                        Ok(latest_version) => {
-                            if latest_version != cmp_ver {
-                                let package_update_data = PackageUpdateData {
-                                    package_name,
-                                    old_version: version,
-                                    new_version: format!("{}{}", ver_prefix, latest_version),
-                                    dev,
-                                };
-
-                                return Some(package_update_data);
-                            }
-                        }
-                        Err(err) => {
-                            println!("Error when fetching {package_name} version, {err}");
-                        }
-                    };
+        let checks_vec = check_all(&registry, &options, &deps).await;
 
-                    None
-                })
-            },
-        )
-        .collect();
+        // 2.0.0 came out 400 days after 1.0.0, 1.1.0 only 59.
+        match &checks_vec[0] {
+            PackageCheck::Update(update) => assert_eq!(update.new_version, "^2.0.0"),
+            other => panic!("Expected an update, got {:?}", other),
+        }
+        assert!(matches!(
+            checks_vec[1],
+            PackageCheck::Skipped {
+                reason: SkipReason::RecentEnough,
+                ..
+            }
+        ));
+    }
 
-    futures
-}
+    #[test]
+    fn test_casing_warning() {
+        assert_eq!(
+            casing_warning("React", Some("react")).as_deref(),
+            Some("React is published as react, package names are case sensitive")
+        );
+        assert_eq!(casing_warning("react", Some("react")), None);
+        assert_eq!(casing_warning("react", None), None);
+    }
 
-/// Gets the latest version of a package via the NPM registry API.
-async fn get_package_version(package_name: &str) -> Result<String, Error> {
-    let url = format!("{}/{}/latest", API_URL, package_name);
+    #[tokio::test]
+    async fn test_casing_warning_returned_with_check() {
+        let url = mock_registry(&[(
+            "/Package-A/latest",
+            200,
+            r#"{"name":"package-a","version":"1.0.0"}"#,
+        )])
+        .await;
+        let registry = registry_at(url);
 
-    let resp = reqwest::get(&url)
-        .await?
-        .json::<GetPackageResponse>()
-        .await?;
+        let (check, warnings) = compare_package_version(
+            registry,
+            Arc::new(CheckOptions::default()),
+            "Package-A".to_string(),
+            "1.0.0".to_string(),
+            DEP_KEY,
+        )
+        .await;
+        assert_eq!(check.package_name(), "Package-A");
+        assert_eq!(
+            warnings,
+            vec!["Package-A is published as package-a, package names are case sensitive"]
+        );
+    }
 
-    Ok(resp.version)
-}
+    #[test]
+    fn test_group_updates_by_bump() {
+        let checks = vec![
+            PackageCheck::Update(update_data("react", "^17.0.0", "^18.2.0")),
+            PackageCheck::Update(update_data("recoil", "0.7.0", "0.7.1")),
+            PackageCheck::Update(update_data("next", "canary", "13.4.0")),
+            PackageCheck::Update(update_data("vue", "^3.2.0", "^3.3.4")),
+            PackageCheck::Update(update_data("svelte", "^3.0.0", "^4.0.0")),
+        ];
 
-/// Inserts new dependencies into the given package_json serde::Value.
-pub fn insert_new_maps(
-    package_json: &mut Value,
-    deps: IndexMap<String, String>,
-    dev_deps: IndexMap<String, String>,
-) -> Result<(), Error> {
-    if let Some(deps_value) = package_json.get_mut(DEP_KEY) {
-        *deps_value = serde_json::to_value(deps)?;
+        let grouped = group_updates_by_bump(checks);
+        let grouped: Vec<&str> = grouped.iter().map(PackageCheck::package_name).collect();
+        assert_eq!(grouped, vec!["react", "svelte", "vue", "recoil", "next"]);
+        assert_eq!(severity_heading(Bump::Major, 2), "Major (2):");
+        assert_eq!(severity_heading(Bump::Other, 1), "Other (1):");
     }
-    if let Some(dev_deps_value) = package_json.get_mut(DEV_DEP_KEY) {
-        *dev_deps_value = serde_json::to_value(dev_deps)?;
+
+    #[test]
+    fn test_group_updates_by_registry() {
+        let from = |name: &str, registry: &str| {
+            PackageCheck::Update(PackageUpdateData {
+                registry: registry.to_string(),
+                ..update_data(name, "^1.0.0", "^2.0.0")
+            })
+        };
+        let checks = vec![
+            from("react", API_URL),
+            from("@acme/ui", "https://npm.acme.dev/"),
+            from("recoil", API_URL),
+            from("@acme/icons", "https://npm.acme.dev/"),
+        ];
+
+        let grouped = group_updates_by_registry(checks);
+        let grouped: Vec<(String, &str)> = grouped
+            .iter()
+            .map(|check| match check {
+                PackageCheck::Update(update) => {
+                    (registry_host(&update.registry), check.package_name())
+                }
+                other => panic!("Expected an update, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            grouped,
+            vec![
+                ("registry.npmjs.org".to_string(), "react"),
+                ("registry.npmjs.org".to_string(), "recoil"),
+                ("npm.acme.dev".to_string(), "@acme/ui"),
+                ("npm.acme.dev".to_string(), "@acme/icons"),
+            ]
+        );
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_bounded_concurrency_keeps_manifest_order() {
+        let routes: Vec<(String, u16, String)> = (0..20)
+            .map(|i| {
+                (
+                    format!("/package-{}/latest", i),
+                    200,
+                    r#"{"version":"2.0.0"}"#.to_string(),
+                )
+            })
+            .collect();
+        let routes: Vec<(&str, u16, &str)> = routes
+            .iter()
+            .map(|(path, status, body)| (path.as_str(), *status, body.as_str()))
+            .collect();
+        let url = mock_registry(&routes).await;
+        let registry = Arc::new(Registry::new(Client::new(), url, None).with_concurrency(3));
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde_json::json;
+        let deps: IndexMap<String, String> = (0..20)
+            .map(|i| (format!("package-{}", i), "^1.0.0".to_string()))
+            .collect();
 
-    #[test]
-    fn test_insert_new_maps() {
+        let checks_vec = check_all(&registry, &Default::default(), &deps).await;
+
+        let names: Vec<&str> = checks_vec.iter().map(PackageCheck::package_name).collect();
+        assert_eq!(names, deps.keys().map(String::as_str).collect::<Vec<_>>());
+        assert!(checks_vec
+            .iter()
+            .all(|check| matches!(check, PackageCheck::Update(_))));
+    }
+
+    #[tokio::test]
+    async fn test_manifest_with_only_dependencies() {
+        let url = mock_registry(&[("/package-a/latest", 200, r#"{"version":"2.0.0"}"#)]).await;
+        let registry = registry_at(url);
         let mut package_json = json!({
-            "name": "abc123",
-            "dependencies": {
-                "package-a": "^1.0.0",
-                "package-b": "^2.0.0",
-            },
-            "devDependencies": {
-                "package-c": "^3.0.0",
-                "package-d": "^4.0.0",
-            }
+            "name": "only-deps",
+            "dependencies": { "package-a": "^1.0.0" }
         });
 
-        let mut deps: IndexMap<String, String> = IndexMap::new();
-        deps.insert("package-a".to_string(), "^2.0.0".to_string());
-        deps.insert("package-b".to_string(), "^3.0.0".to_string());
+        let mut deps = dependency_section(&package_json, DEP_KEY).unwrap();
+        let dev_deps = dependency_section(&package_json, DEV_DEP_KEY).unwrap();
+        assert!(dev_deps.is_empty());
 
-        let mut dev_deps: IndexMap<String, String> = IndexMap::new();
-        dev_deps.insert("package-c".to_string(), "^3.5.0".to_string());
-        dev_deps.insert("package-d".to_string(), "^4.0.0".to_string());
+        let checks_vec = check_all(&registry, &Default::default(), &deps).await;
+        for check in checks_vec {
+            if let PackageCheck::Update(update) = check {
+                deps.insert(update.package_name, update.new_version);
+            }
+        }
 
-        // Expect the new maps to be inserted into the package.json file.
-        let result = insert_new_maps(&mut package_json, deps, dev_deps);
-        assert!(result.is_ok());
+        insert_new_maps(
+            &mut package_json,
+            deps,
+            dev_deps,
+            IndexMap::new(),
+            IndexMap::new(),
+        )
+        .unwrap();
         assert_eq!(
             package_json,
             json!({
-                "name": "abc123",
-                "dependencies": {
-                    "package-a": "^2.0.0",
-                    "package-b": "^3.0.0",
-                },
-                "devDependencies": {
-                    "package-c": "^3.5.0",
-                    "package-d": "^4.0.0",
-                }
+                "name": "only-deps",
+                "dependencies": { "package-a": "^2.0.0" }
             })
         );
     }
 
-    #[tokio::test]
-    async fn test_get_package_version() {
-        let package = "react";
-        let package_version = get_package_version(package).await;
-        assert!(package_version.is_ok());
-        assert_ne!(package_version.unwrap(), "0.0.0");
+    #[test]
+    fn test_partial_failure_status() {
+        assert_eq!(
+            partial_failure_status(10, 2, true).as_deref(),
+            Some("Updated 10 packages; 2 failed to check (see above).")
+        );
+        assert_eq!(
+            partial_failure_status(1, 1, false).as_deref(),
+            Some("Found updates for 1 package; 1 failed to check (see above).")
+        );
+        assert_eq!(partial_failure_status(10, 0, true), None);
     }
 
     #[tokio::test]
-    async fn test_get_package_version_non_existant() {
-        let package = "non-existant-package_lol_123123";
-        let package_version = get_package_version(package).await;
-        assert!(package_version.is_err());
+    async fn test_target_honors_declared_range() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"3.0.0"}"#),
+            ("/package-a/1.2.5", 200, r#"{"version":"1.2.5"}"#),
+            ("/package-a/1.4.0", 200, r#"{"version":"1.4.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{"versions":{"1.2.0":{},"1.2.5":{},"1.3.0-beta.1":{},"1.4.0":{},"2.0.0":{},"3.0.0":{}}}"#,
+            ),
+        ])
+        .await;
+        let registry = registry_at(url);
+
+        for (target, spec, expected) in [
+            (Target::Latest, "~1.2.0", "~3.0.0"),
+            (Target::Minor, "~1.2.0", "~1.2.5"),
+            (Target::Minor, "^1.2.0", "^1.4.0"),
+            (Target::Minor, "1.2.0", "1.4.0"),
+            (Target::Patch, "^1.2.0", "^1.2.5"),
+            // Partial versions stay within the target rather than widening to latest.
+            (Target::Minor, "^1.2", "^1.4.0"),
+            (Target::Patch, "1.2", "1.2.5"),
+        ] {
+            let options = Arc::new(CheckOptions {
+                target,
+                ..Default::default()
+            });
+            let mut deps: IndexMap<String, String> = IndexMap::new();
+            deps.insert("package-a".to_string(), spec.to_string());
+
+            let checks_vec = check_all(&registry, &options, &deps).await;
+
+            match &checks_vec[0] {
+                PackageCheck::Update(update) => {
+                    assert_eq!(update.new_version, expected, "{} {}", target, spec)
+                }
+                other => panic!("Expected an update, got {:?}", other),
+            }
+        }
+        assert!(target_range(Target::Minor, "", "1.2.3.4").is_none());
     }
 
     #[tokio::test]
-    async fn test_process_dependencies_and_await_futures() {
+    async fn test_target_shares_the_packument() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.0.0"}"#),
+            ("/package-a/1.4.0", 200, r#"{"version":"1.4.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{"versions":{"1.2.0":{},"1.2.5":{},"1.4.0":{},"2.0.0":{}}}"#,
+            ),
+        ])
+        .await;
+        let registry = registry_at(url);
+        let options = Arc::new(CheckOptions {
+            target: Target::Minor,
+            compared_targets: vec![Target::Patch],
+            since_tag: true,
+            ..Default::default()
+        });
         let mut deps: IndexMap<String, String> = IndexMap::new();
-        deps.insert("react".to_string(), "^2.0.0".to_string());
-        deps.insert("recoil".to_string(), "~3.0.0".to_string());
+        deps.insert("package-a".to_string(), "^1.2.0".to_string());
 
-        let futures = process_dependencies(&deps, false).await;
-        assert_eq!(futures.len(), 2);
+        let checks_vec = check_all(&registry, &options, &deps).await;
 
-        let mut pb = ProgressBar::new(2);
-        pb.show_bar = false;
-        pb.show_counter = false;
-        pb.show_message = false;
-        pb.show_percent = false;
-        pb.show_time_left = false;
-        pb.show_speed = false;
+        match &checks_vec[0] {
+            PackageCheck::Update(update) => assert_eq!(update.new_version, "^1.4.0"),
+            other => panic!("Expected an update, got {:?}", other),
+        }
+        // The latest version, the packument once for all three uses, and the 1.4.0 manifest.
+        assert_eq!(registry.stats.requests.load(Ordering::Relaxed), 3);
+    }
 
-        let mut updates_vec: Vec<PackageUpdateData> = vec![];
+    #[tokio::test]
+    async fn test_targets_per_section() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.1.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{"versions":{"1.2.0":{},"1.4.0":{},"2.0.0":{},"2.1.0":{}}}"#,
+            ),
+            ("/package-a/1.4.0", 200, r#"{"version":"1.4.0"}"#),
+            ("/package-a/1.2.0", 200, r#"{"version":"1.2.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
+        let matches = cli()
+            .try_get_matches_from(["ncu-rs", "--target-prod", "minor", "--target-dev", "latest"])
+            .unwrap();
+        let options = Arc::new(CheckOptions {
+            prod_target: matches
+                .value_of("target-prod")
+                .map(str::parse)
+                .transpose()
+                .unwrap(),
+            dev_target: matches
+                .value_of("target-dev")
+                .map(str::parse)
+                .transpose()
+                .unwrap(),
+            target: Target::Patch,
+            ..Default::default()
+        });
 
-        let futures = await_futures(futures, &mut pb, &mut updates_vec).await;
-        assert!(futures.is_ok());
+        for (section, expected) in [
+            (DEP_KEY, Some("^1.4.0")),
+            (DEV_DEP_KEY, Some("^2.1.0")),
+            // Anything else falls back to --target, which has nothing newer.
+            (PEER_DEP_KEY, None),
+        ] {
+            let check = check_version(
+                registry.clone(),
+                options.clone(),
+                "package-a".to_string(),
+                "package-a".to_string(),
+                "^1.2.0".to_string(),
+                section,
+                &mut vec![],
+            )
+            .await;
+            match (check, expected) {
+                (PackageCheck::Update(update), Some(expected)) => {
+                    assert_eq!(update.new_version, expected)
+                }
+                (PackageCheck::Skipped { reason, .. }, None) => {
+                    assert_eq!(reason, SkipReason::AlreadyLatest)
+                }
+                (other, _) => panic!("unexpected {:?} for {}", other, section),
+            }
+        }
+    }
 
-        for update in updates_vec {
-            if update.package_name == "react" {
-                assert_eq!(update.old_version, "^2.0.0");
-                assert_ne!(update.old_version, update.new_version);
-            } else if update.package_name == "recoil" {
-                assert_eq!(update.old_version, "~3.0.0");
-                assert_ne!(update.old_version, update.new_version);
-            } else {
-                panic!("Unexpected package name: {}", update.package_name);
+    #[tokio::test]
+    async fn test_compared_targets_side_by_side() {
+        let url = mock_registry(&[
+            ("/package-a/latest", 200, r#"{"version":"2.1.0"}"#),
+            (
+                "/package-a",
+                200,
+                r#"{"versions":{"1.2.0":{},"1.2.5":{},"1.4.0":{},"2.0.0":{},"2.1.0":{}}}"#,
+            ),
+        ])
+        .await;
+        let registry = registry_at(url);
+        let options = Arc::new(CheckOptions {
+            compared_targets: vec![Target::Minor, Target::Patch],
+            ..Default::default()
+        });
+
+        let check = check_version(
+            registry,
+            options,
+            "package-a".to_string(),
+            "package-a".to_string(),
+            "^1.2.0".to_string(),
+            DEP_KEY,
+            &mut vec![],
+        )
+        .await;
+        match check {
+            PackageCheck::Update(update) => {
+                assert_eq!(update.new_version, "^2.1.0");
+                assert_eq!(
+                    update.compared,
+                    [
+                        (Target::Minor, Some("1.4.0".to_string())),
+                        (Target::Patch, Some("1.2.5".to_string()))
+                    ]
+                );
+                let json = updates_json(&[PackageCheck::Update(update)], &[]);
+                assert_eq!(
+                    json["updates"][0]["targets"],
+                    json!({ "minor": "1.4.0", "patch": "1.2.5" })
+                );
             }
+            other => panic!("expected an update, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_resolve_targets() {
+        assert_eq!(resolve_targets(None, None).unwrap(), [Target::Latest]);
+        assert_eq!(
+            resolve_targets(None, Some("minor")).unwrap(),
+            [Target::Minor]
+        );
+        assert_eq!(
+            resolve_targets(Some("patch"), Some("minor")).unwrap(),
+            [Target::Patch]
+        );
+        assert_eq!(
+            resolve_targets(Some("latest, minor"), None).unwrap(),
+            [Target::Latest, Target::Minor]
+        );
+        assert!(resolve_targets(None, Some("newest")).is_err());
+        assert!(resolve_targets(Some("latest,newest"), None).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_non_numeric_specs_are_left_untouched() {
+        let specs = [
+            "*",
+            "latest",
+            "1.2.x",
+            "git+https://github.com/foo/bar",
+            "workspace:^1.0.0",
+            "file:../local-package",
+            "1.0.0 - 2.0.0",
+        ];
+        let routes: Vec<String> = (0..specs.len())
+            .map(|i| format!("/package-{}/latest", i))
+            .collect();
+        let routes: Vec<(&str, u16, &str)> = routes
+            .iter()
+            .map(|route| (route.as_str(), 200, r#"{"version":"2.0.0"}"#))
+            .collect();
+        let url = mock_registry(&routes).await;
+        let registry = registry_at(url);
+
+        let deps: IndexMap<String, String> = specs
+            .iter()
+            .enumerate()
+            .map(|(i, spec)| (format!("package-{}", i), spec.to_string()))
+            .collect();
+
+        let checks_vec = check_all(&registry, &Default::default(), &deps).await;
+
+        for (check, spec) in checks_vec.iter().zip(specs) {
+            assert!(
+                matches!(check, PackageCheck::Skipped { .. }),
+                "{} should be skipped, got {:?}",
+                spec,
+                check
+            );
         }
+        assert!(unsupported_spec("^1.2.0").is_none());
+        assert!(unsupported_spec("1.2").is_none());
+        assert!(unsupported_spec(">=1.0.0").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_peer_and_optional_sections_are_checked() {
+        let url = mock_registry(&[
+            ("/react/latest", 200, r#"{"version":"18.2.0"}"#),
+            (
+                "/fsevents/latest",
+                200,
+                r#"{"version":"2.3.3","os":["darwin"]}"#,
+            ),
+            ("/left-pad/latest", 200, r#"{"version":"1.3.0"}"#),
+        ])
+        .await;
+        let registry = registry_at(url);
+        let package_json = json!({
+            "name": "abc123",
+            "peerDependencies": { "react": "^17.0.0" },
+            "optionalDependencies": { "fsevents": "^2.0.0", "left-pad": "^1.0.0" }
+        });
+        let peer_deps = dependency_section(&package_json, PEER_DEP_KEY).unwrap();
+        let optional_deps = dependency_section(&package_json, OPTIONAL_DEP_KEY).unwrap();
+
+        let options = Arc::new(CheckOptions {
+            platform: Some(("linux".to_string(), "x64".to_string())),
+            ..Default::default()
+        });
+        let mut checks_vec = check_section(&registry, &options, &peer_deps, PEER_DEP_KEY).await;
+        checks_vec
+            .extend(check_section(&registry, &options, &optional_deps, OPTIONAL_DEP_KEY).await);
+
+        let peer = match &checks_vec[0] {
+            PackageCheck::Update(update) => update,
+            other => panic!("Expected an update, got {:?}", other),
+        };
+        assert_eq!(peer.new_version, "^18.2.0");
+        // Peer updates are reported, but only written with --write-peer.
+        assert!(held_back_reason(peer, false, false).is_some());
+        assert_eq!(held_back_reason(peer, false, true), None);
+
+        // fsevents only supports macOS.
+        assert!(matches!(
+            checks_vec[1],
+            PackageCheck::Skipped {
+                reason: SkipReason::OtherPlatform,
+                ..
+            }
+        ));
+        assert!(
+            matches!(&checks_vec[2], PackageCheck::Update(update) if update.section == OPTIONAL_DEP_KEY)
+        );
+
+        let options = Arc::new(CheckOptions {
+            skipped_sections: skipped_sections(Some("prod,dev")).unwrap(),
+            ..Default::default()
+        });
+        assert!(
+            process_dependencies(&registry, &options, &peer_deps, PEER_DEP_KEY)
+                .await
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_skipped_sections() {
+        assert!(skipped_sections(None).unwrap().is_empty());
+        assert_eq!(
+            skipped_sections(Some("prod, dev")).unwrap(),
+            [PEER_DEP_KEY, OPTIONAL_DEP_KEY]
+        );
+        assert!(skipped_sections(Some("prod,bundled")).is_err());
+    }
+
+    #[test]
+    fn test_only_prod_skips_all_but_dependencies() {
+        let matches = cli()
+            .try_get_matches_from(["ncu-rs", "--only-prod"])
+            .unwrap();
+        assert_eq!(
+            skipped_sections(dep_types(&matches)).unwrap(),
+            [DEV_DEP_KEY, PEER_DEP_KEY, OPTIONAL_DEP_KEY]
+        );
+        assert!(cli()
+            .try_get_matches_from(["ncu-rs", "--only-prod", "--dep", "dev"])
+            .is_err());
+    }
+
+    #[test]
+    fn test_insert_new_maps_leaves_absent_sections_out() {
+        let mut package_json = json!({
+            "name": "abc123",
+            "dependencies": { "package-a": "^1.0.0" },
+            "peerDependencies": { "package-b": "^1.0.0" }
+        });
+
+        let deps = dependency_section(&package_json, DEP_KEY).unwrap();
+        let mut peer_deps: IndexMap<String, String> = IndexMap::new();
+        peer_deps.insert("package-b".to_string(), "^2.0.0".to_string());
+        insert_new_maps(
+            &mut package_json,
+            deps,
+            IndexMap::new(),
+            peer_deps,
+            IndexMap::new(),
+        )
+        .unwrap();
+
+        let keys: Vec<&String> = package_json.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["name", "dependencies", "peerDependencies"]);
+        assert_eq!(package_json[PEER_DEP_KEY]["package-b"], "^2.0.0");
+    }
+
+    #[test]
+    fn test_write_absent_section_in_stable_position() {
+        let mut package_json = json!({
+            "name": "abc123",
+            "dependencies": { "package-a": "^1.0.0" },
+            "resolutions": { "package-b": "1.0.0" },
+            "scripts": { "test": "jest" }
+        });
+
+        let mut dev_deps: IndexMap<String, String> = IndexMap::new();
+        write_section(&mut package_json, DEV_DEP_KEY, dev_deps.clone()).unwrap();
+        assert!(package_json.get(DEV_DEP_KEY).is_none());
+
+        dev_deps.insert("package-c".to_string(), "^3.0.0".to_string());
+        write_section(&mut package_json, DEV_DEP_KEY, dev_deps).unwrap();
+        let keys: Vec<&String> = package_json.as_object().unwrap().keys().collect();
+        assert_eq!(
+            keys,
+            [
+                "name",
+                "dependencies",
+                "devDependencies",
+                "resolutions",
+                "scripts"
+            ]
+        );
+
+        let mut package_json = json!({ "name": "abc123", "resolutions": {} });
+        let mut deps: IndexMap<String, String> = IndexMap::new();
+        deps.insert("package-a".to_string(), "^1.0.0".to_string());
+        write_section(&mut package_json, DEP_KEY, deps).unwrap();
+        let keys: Vec<&String> = package_json.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["name", "dependencies", "resolutions"]);
     }
 }
@@ -0,0 +1,270 @@
+//! Finding the package.json files of a monorepo and relating them to each other.
+
+use indexmap::IndexMap;
+use serde::Serialize;
+use serde_json::Value;
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{coerce_version, jsonc, strip_bom, DEP_TYPES};
+
+const MANIFEST_FILE: &str = "package.json";
+const WORKSPACE_PROTOCOL: &str = "workspace:";
+
+/// A dependency of one workspace package on another.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+/// The workspace packages and which of them depend on each other through `workspace:` specs.
+#[derive(Debug, Serialize)]
+pub struct Graph {
+    pub nodes: Vec<String>,
+    pub edges: Vec<Edge>,
+}
+
+impl Graph {
+    /// Renders the graph in Graphviz's DOT language.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph workspace {\n");
+        for node in &self.nodes {
+            dot.push_str(&format!("    {:?};\n", node));
+        }
+        for edge in &self.edges {
+            dot.push_str(&format!("    {:?} -> {:?};\n", edge.from, edge.to));
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Lists every package.json in `root` and the directories below it, at most `max_depth` levels
+/// down. `node_modules` and hidden directories are skipped. The root's own manifest comes first,
+/// then the rest in path order.
+pub fn find_manifests(root: &Path, max_depth: usize) -> Vec<PathBuf> {
+    let mut manifests = vec![];
+    let manifest = root.join(MANIFEST_FILE);
+    if manifest.is_file() {
+        manifests.push(manifest);
+    }
+    if max_depth == 0 {
+        return manifests;
+    }
+
+    let mut dirs: Vec<PathBuf> = fs::read_dir(root)
+        .map(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_dir()))
+                .filter(|entry| {
+                    let name = entry.file_name();
+                    let name = name.to_string_lossy();
+                    name != "node_modules" && !name.starts_with('.')
+                })
+                .map(|entry| entry.path())
+                .collect()
+        })
+        .unwrap_or_default();
+    dirs.sort();
+
+    for dir in dirs {
+        manifests.extend(find_manifests(&dir, max_depth - 1));
+    }
+    manifests
+}
+
+/// Reads and parses the given manifests, leaving out any that can't be. Those are reported
+/// when they are checked.
+pub fn read_manifests(paths: &[PathBuf]) -> Vec<(PathBuf, Value)> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            let contents = fs::read_to_string(path).ok()?;
+            let contents = strip_bom(&contents).0;
+            let manifest = serde_json::from_str(&jsonc::strip_comments(contents)).ok()?;
+            Some((path.clone(), manifest))
+        })
+        .collect()
+}
+
+/// Warns about dependencies declared at different majors in different manifests, such as react
+/// 17 in one package and 18 in another, which tends to cause conflicts at runtime.
+pub fn duplicate_majors(manifests: &[(PathBuf, Value)]) -> Vec<String> {
+    let mut majors: IndexMap<&str, IndexMap<u64, Vec<String>>> = IndexMap::new();
+    for (path, manifest) in manifests {
+        for (package_name, spec) in dependencies(manifest) {
+            let major = match coerce_version(spec.trim_start_matches(['^', '~'])) {
+                Some(version) => version.major,
+                None => continue,
+            };
+            let paths = majors
+                .entry(package_name)
+                .or_default()
+                .entry(major)
+                .or_default();
+            let path = path.display().to_string();
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    majors
+        .into_iter()
+        .filter(|(_, majors)| majors.len() > 1)
+        .map(|(package_name, majors)| {
+            let majors: Vec<String> = majors
+                .into_iter()
+                .map(|(major, paths)| format!("{} in {}", major, paths.join(", ")))
+                .collect();
+            format!(
+                "{} is declared at different majors across the workspace: {}",
+                package_name,
+                majors.join("; ")
+            )
+        })
+        .collect()
+}
+
+/// Builds the graph of `workspace:` dependencies between the named packages of a workspace.
+pub fn graph(manifests: &[(PathBuf, Value)]) -> Graph {
+    let nodes: Vec<String> = manifests
+        .iter()
+        .filter_map(|(_, manifest)| manifest["name"].as_str().map(String::from))
+        .collect();
+
+    let mut edges = vec![];
+    for (_, manifest) in manifests {
+        let from = match manifest["name"].as_str() {
+            Some(name) => name,
+            None => continue,
+        };
+        for (package_name, spec) in dependencies(manifest) {
+            let edge = Edge {
+                from: from.to_string(),
+                to: package_name.to_string(),
+            };
+            if spec.starts_with(WORKSPACE_PROTOCOL)
+                && nodes.iter().any(|node| node == package_name)
+                && !edges.contains(&edge)
+            {
+                edges.push(edge);
+            }
+        }
+    }
+
+    Graph { nodes, edges }
+}
+
+/// Every `(name, spec)` in the dependency sections of a manifest.
+fn dependencies(manifest: &Value) -> impl Iterator<Item = (&str, &str)> {
+    DEP_TYPES
+        .iter()
+        .filter_map(|(_, section)| manifest[*section].as_object())
+        .flatten()
+        .filter_map(|(package_name, spec)| Some((package_name.as_str(), spec.as_str()?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+    use serde_json::json;
+
+    fn manifest(path: &str, manifest: Value) -> (PathBuf, Value) {
+        (PathBuf::from(path), manifest)
+    }
+
+    #[test]
+    fn test_find_manifests() {
+        let root = TestDir::new("find-manifests");
+        for dir in [
+            "",
+            "packages/a",
+            "packages/b",
+            "packages/a/node_modules/dep",
+            ".git/hooks",
+            "packages/b/src/deep/deeper",
+        ] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join(MANIFEST_FILE), "{}").unwrap();
+        }
+
+        let manifests: Vec<PathBuf> = find_manifests(&root, 3)
+            .into_iter()
+            .map(|path| path.strip_prefix(&root).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(
+            manifests,
+            vec![
+                PathBuf::from("package.json"),
+                PathBuf::from("packages/a/package.json"),
+                PathBuf::from("packages/b/package.json"),
+            ]
+        );
+        assert_eq!(find_manifests(&root, 0).len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_majors_across_files() {
+        let manifests = vec![
+            manifest(
+                "packages/a/package.json",
+                json!({ "dependencies": { "react": "^17.0.2", "lodash": "^4.17.0" } }),
+            ),
+            manifest(
+                "packages/b/package.json",
+                json!({
+                    "dependencies": { "lodash": "~4.17.21" },
+                    "devDependencies": { "react": "18.2.0" }
+                }),
+            ),
+        ];
+
+        assert_eq!(
+            duplicate_majors(&manifests),
+            vec![
+                "react is declared at different majors across the workspace: \
+                 17 in packages/a/package.json; 18 in packages/b/package.json"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_graph_edges() {
+        let manifests = vec![
+            manifest("package.json", json!({ "name": "root" })),
+            manifest(
+                "packages/app/package.json",
+                json!({
+                    "name": "app",
+                    "dependencies": { "ui": "workspace:*", "react": "^18.2.0" },
+                    "devDependencies": { "config": "workspace:^1.0.0" }
+                }),
+            ),
+            manifest(
+                "packages/ui/package.json",
+                json!({ "name": "ui", "dependencies": { "config": "workspace:*" } }),
+            ),
+            manifest("packages/config/package.json", json!({ "name": "config" })),
+        ];
+
+        let graph = graph(&manifests);
+        assert_eq!(graph.nodes, vec!["root", "app", "ui", "config"]);
+        let edges: Vec<(&str, &str)> = graph
+            .edges
+            .iter()
+            .map(|edge| (edge.from.as_str(), edge.to.as_str()))
+            .collect();
+        assert_eq!(
+            edges,
+            vec![("app", "ui"), ("app", "config"), ("ui", "config")]
+        );
+        assert!(graph.to_dot().contains("    \"app\" -> \"ui\";\n"));
+    }
+}
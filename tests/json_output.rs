@@ -0,0 +1,86 @@
+//! Runs the binary with `--json`, whose stdout must stay a single JSON document whatever else
+//! the run has to say.
+
+use std::{
+    env, fs,
+    io::{Read, Write},
+    net::TcpListener,
+    process::Command,
+    thread,
+};
+
+use serde_json::Value;
+
+/// Serves the given `(path, body)` routes on a local port, answering anything else with a 404.
+fn mock_registry(routes: &'static [(&'static str, &'static str)]) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    thread::spawn(move || {
+        for mut socket in listener.incoming().flatten() {
+            thread::spawn(move || {
+                let mut buf = vec![0; 8192];
+                let n = socket.read(&mut buf).unwrap_or(0);
+                let request = String::from_utf8_lossy(&buf[..n]);
+                let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+                let (status, body) = routes
+                    .iter()
+                    .find(|(route, _)| *route == path)
+                    .map_or((404, "{}"), |(_, body)| (200, body));
+                let response = format!(
+                    "HTTP/1.1 {} MOCK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes());
+            });
+        }
+    });
+
+    format!("http://{}", address)
+}
+
+#[test]
+fn test_json_stdout_is_one_document() {
+    let url = mock_registry(&[
+        ("/package-a/latest", r#"{"version":"2.0.0"}"#),
+        ("/package-a", r#"{"versions":{"1.0.0":{},"2.0.0":{}}}"#),
+        ("/package-b/latest", r#"{"version":"1.2.0"}"#),
+        ("/package-b", r#"{"versions":{"1.2.0":{}}}"#),
+    ]);
+    let dir = env::temp_dir().join(format!("ncu-rs-test-json-output-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("package.json");
+    // A numeric version, an outdated package and a package the registry doesn't know.
+    fs::write(
+        &path,
+        r#"{ "dependencies": { "package-a": "^1.0.0", "package-b": 1.2, "missing": "^1.0.0" } }"#,
+    )
+    .unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_ncu-rs"))
+        .args(["--json", "--registry", &url, "--no-cache", "--explain"])
+        .args(["--include-deprecated-current", "--compare-to-installed"])
+        .args(["-y", "--install-command", "true"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    let written = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_dir_all(&dir);
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    if let Err(err) = serde_json::from_str::<Value>(&stdout) {
+        panic!("stdout isn't one JSON document ({}):\n{}", err, stdout);
+    }
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Error when fetching missing version"),
+        "{}",
+        stderr
+    );
+    assert!(stderr.contains("package-b is the number 1.2"), "{}", stderr);
+    assert!(written.contains(r#""package-a": "^2.0.0""#), "{}", written);
+}